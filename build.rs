@@ -0,0 +1,19 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    // Client and server of the gRPC service, with the vendored protoc
+    // unless PROTOC points to another one
+    #[cfg(feature = "grpc")]
+    {
+        if std::env::var_os("PROTOC").is_none() {
+            let protoc = protoc_bin_vendored::protoc_bin_path()
+                .expect("ERROR: No vendored protoc for this platform, set PROTOC instead.");
+            std::env::set_var("PROTOC", protoc);
+        }
+        // Without the connect() of the client, which needs the edition 2021
+        // prelude: clients connect a Channel instead
+        tonic_build::configure()
+            .build_transport(false)
+            .compile_protos(&["proto/sequence_generator.proto"], &["proto"])
+            .unwrap_or_else(|error| panic!("ERROR: Could not compile the gRPC service: {}", error));
+    }
+}
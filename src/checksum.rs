@@ -0,0 +1,122 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Algorithm used to fill the checksum field of the ID. The checksum is
+/// calculated over the ID with the checksum bits set to zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ChecksumAlgorithm {
+    /// Lower bits of the CRC-16/CCITT of the big-endian bytes of the ID.
+    /// Accepts between 1 and 16 bits.
+    Crc,
+    /// Damm check digit (0-9) of the decimal representation of the ID with
+    /// the checksum bits cleared. Requires at least 4 bits.
+    Damm,
+}
+
+impl ChecksumAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Crc => "crc",
+            ChecksumAlgorithm::Damm => "damm",
+        }
+    }
+    pub fn min_bits(&self) -> u8 {
+        match self {
+            ChecksumAlgorithm::Crc => 1,
+            ChecksumAlgorithm::Damm => 4,
+        }
+    }
+    pub fn max_bits(&self) -> u8 {
+        16
+    }
+    /// Checksum of `payload` truncated to `bits`.
+    pub fn checksum(&self, payload: u64, bits: u8) -> u64 {
+        let mask = (1_u64 << bits) - 1;
+        match self {
+            ChecksumAlgorithm::Crc => crc16(&payload.to_be_bytes()) as u64 & mask,
+            ChecksumAlgorithm::Damm => damm(payload) as u64 & mask,
+        }
+    }
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "crc" | "crc16" => Ok(ChecksumAlgorithm::Crc),
+            "damm" => Ok(ChecksumAlgorithm::Damm),
+            _ => Err(format!(
+                "ERROR: Unknown checksum algorithm '{}'. Expected one of 'crc' or 'damm'",
+                value
+            )),
+        }
+    }
+}
+
+/// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF.
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+const DAMM_TABLE: [[u8; 10]; 10] = [
+    [0, 3, 1, 7, 5, 9, 8, 6, 4, 2],
+    [7, 0, 9, 2, 1, 5, 4, 8, 6, 3],
+    [4, 2, 0, 6, 8, 7, 1, 3, 5, 9],
+    [1, 7, 5, 0, 9, 8, 3, 4, 2, 6],
+    [6, 1, 2, 3, 0, 4, 5, 9, 7, 8],
+    [3, 6, 7, 4, 2, 0, 9, 5, 8, 1],
+    [5, 8, 6, 9, 7, 2, 0, 1, 3, 4],
+    [8, 9, 4, 5, 3, 6, 2, 0, 1, 7],
+    [9, 4, 3, 8, 6, 1, 7, 2, 0, 5],
+    [2, 5, 8, 1, 4, 3, 6, 7, 9, 0],
+];
+
+fn damm(value: u64) -> u8 {
+    value.to_string().bytes().fold(0_u8, |interim, digit| {
+        DAMM_TABLE[interim as usize][(digit - b'0') as usize]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn known_values() {
+        use super::*;
+        // CRC-16/CCITT-FALSE check value for "123456789"
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        // Damm check digit for 572 is 4, and appending it validates to 0
+        assert_eq!(damm(572), 4);
+        assert_eq!(damm(5724), 0);
+        assert_eq!(
+            ChecksumAlgorithm::Crc.checksum(0, 4),
+            crc16(&[0; 8]) as u64 & 0xF
+        );
+        assert_eq!(
+            "DAMM".parse::<ChecksumAlgorithm>(),
+            Ok(ChecksumAlgorithm::Damm)
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! `bounds` subcommand, printing the range of IDs generated during a time
+//! interval, e.g. to query tables keyed by ID.
+use super::error::CliError;
+use ::sequence_generator::sequence_generator::{self, SequenceProperties};
+use std::time::SystemTime;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+#[derive(clap::Args, Debug)]
+pub struct BoundsOpt {
+    #[clap(
+        long = "--from",
+        help = "Start of the interval in RFC3339 format, included. Truncated to the timestamp tick."
+    )]
+    from: String,
+    #[clap(
+        long = "--to",
+        help = "End of the interval in RFC3339 format, excluded. Truncated to the timestamp tick."
+    )]
+    to: String,
+    #[clap(
+        long = "--sql",
+        help = "Print a SQL 'BETWEEN' clause instead of the minimum and maximum IDs."
+    )]
+    sql: bool,
+    #[clap(
+        long = "--column",
+        default_value = "id",
+        help = "Column compared in the SQL clause."
+    )]
+    column: String,
+}
+
+pub fn run(properties: SequenceProperties, options: BoundsOpt) -> Result<(), CliError> {
+    let (min, max) = bounds(&properties, &options.from, &options.to).map_err(CliError::usage)?;
+    if options.sql {
+        println!("{} BETWEEN {} AND {}", options.column, min, max);
+    } else {
+        println!("min: {}\nmax: {}", min, max);
+    }
+    Ok(())
+}
+
+/// Smallest ID at `from` and largest ID before `to`, both included.
+fn bounds(properties: &SequenceProperties, from: &str, to: &str) -> Result<(u64, u64), String> {
+    let min = min_id_at(properties, from)?;
+    let end = min_id_at(properties, to)?;
+    if end <= min {
+        return Err(format!(
+            "Interval from '{}' to '{}' contains no timestamp tick",
+            from, to
+        ));
+    }
+    Ok((min, end - 1))
+}
+
+fn min_id_at(properties: &SequenceProperties, time: &str) -> Result<u64, String> {
+    let parsed = OffsetDateTime::parse(time, &Rfc3339)
+        .map_err(|error| format!("Invalid RFC3339 time '{}': {}", time, error))?;
+    sequence_generator::min_id_at(SystemTime::from(parsed), properties)
+        .map_err(|error| format!("No IDs at '{}': {}", time, error))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn interval_bounds() {
+        use super::*;
+        use std::time::{Duration, UNIX_EPOCH};
+        // 2020-01-01T00:00:00Z, in milliseconds
+        let custom_epoch = UNIX_EPOCH + Duration::from_secs(1_577_836_800);
+        let properties = SequenceProperties::new(custom_epoch, 9, 0, 11, 3, 0, 1500);
+        let (min, max) = bounds(
+            &properties,
+            "2020-01-01T00:00:01Z",
+            "2020-01-01T00:00:02+00:00",
+        )
+        .unwrap();
+        assert_eq!(min, 1000 << 20);
+        assert_eq!(max, (2000 << 20) - 1);
+        assert!(bounds(&properties, "2020-01-01T00:00:02Z", "2020-01-01T00:00:02Z").is_err());
+        assert!(bounds(&properties, "2019-12-31T00:00:00Z", "2020-01-01T00:00:02Z").is_err());
+        assert!(bounds(&properties, "yesterday", "2020-01-01T00:00:02Z").is_err());
+    }
+}
@@ -0,0 +1,199 @@
+//! TOML/JSON configuration file given with `--config`. Every key is optional
+//! and only fills the parameters not given on the command line.
+use crate::Opt;
+use ::sequence_generator::config::{ChecksumConfig, FieldConfig, LayoutVersionConfig};
+use serde::de::{Deserializer, Error as _};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub preset: Option<String>,
+    #[serde(default, deserialize_with = "custom_epoch")]
+    pub custom_epoch: Option<String>,
+    #[serde(default, deserialize_with = "bits::<_, 1, 16>")]
+    pub node_id_bits: Option<u8>,
+    pub node_id: Option<u16>,
+    pub node_id_source: Option<String>,
+    pub node_id_lease_dir: Option<String>,
+    pub coordinator: Option<String>,
+    #[serde(default, deserialize_with = "bits::<_, 1, 16>")]
+    pub sequence_bits: Option<u8>,
+    #[serde(default, deserialize_with = "bits::<_, 0, 18>")]
+    pub micros_ten_power: Option<u8>,
+    #[serde(default, alias = "sign_bits", deserialize_with = "bits::<_, 0, 7>")]
+    pub unused_bits: Option<u8>,
+    #[serde(alias = "backoff_cooldown_start_ns")]
+    pub cooldown_ns: Option<u64>,
+    #[serde(default, deserialize_with = "field_order")]
+    pub field_order: Option<String>,
+    pub node_subfields: Option<Vec<FieldConfig>>,
+    pub extra_fields: Option<Vec<FieldConfig>>,
+    pub checksum: Option<ChecksumConfig>,
+    pub layout_version: Option<LayoutVersionConfig>,
+}
+
+impl ConfigFile {
+    /// Read a `.toml` or `.json` file. Files with other extensions are parsed
+    /// as JSON if they start with '{' and as TOML otherwise. Errors include
+    /// the line and column of the offending value.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents =
+            fs::read_to_string(path).map_err(|error| format!("could not be read: {}", error))?;
+        let is_json = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => true,
+            Some("toml") => false,
+            _ => contents.trim_start().starts_with('{'),
+        };
+        ConfigFile::parse(&contents, is_json)
+    }
+
+    pub fn parse(contents: &str, is_json: bool) -> Result<Self, String> {
+        if is_json {
+            serde_json::from_str(contents).map_err(|error| error.to_string())
+        } else {
+            toml::from_str(contents).map_err(|error| error.to_string())
+        }
+    }
+
+    /// Fill the parameters of `args` that were not given on the command line.
+    /// Node ID subfields are returned separately, as the command line only
+    /// supports datacenter, worker and process subfields.
+    pub fn apply(self, args: &mut Opt, node_subfields: &mut Vec<FieldConfig>) {
+        if args.preset.is_none() {
+            args.preset = self.preset;
+        }
+        if args.custom_epoch.is_none() {
+            args.custom_epoch = self.custom_epoch;
+        }
+        if args.node_id_bits.is_none() {
+            args.node_id_bits = self.node_id_bits;
+        }
+        if args.node_id.is_none() {
+            args.node_id = self.node_id;
+        }
+        if args.node_id_source.is_none() {
+            args.node_id_source = self.node_id_source;
+        }
+        if args.node_id_lease_dir.is_none() {
+            args.node_id_lease_dir = self.node_id_lease_dir;
+        }
+        if args.coordinator.is_none() {
+            args.coordinator = self.coordinator;
+        }
+        if args.sequence_bits.is_none() {
+            args.sequence_bits = self.sequence_bits;
+        }
+        if args.micros_ten_power.is_none() {
+            args.micros_ten_power = self.micros_ten_power;
+        }
+        if args.unused_bits.is_none() && args.sign_bits.is_none() {
+            args.unused_bits = self.unused_bits;
+        }
+        if args.cooldown_ns.is_none() {
+            args.cooldown_ns = self.cooldown_ns;
+        }
+        if args.field_order.is_none() {
+            args.field_order = self.field_order;
+        }
+        if let Some(subfields) = self.node_subfields {
+            *node_subfields = subfields;
+        }
+        if args.extra_field.is_empty() {
+            if let Some(extra_fields) = self.extra_fields {
+                args.extra_field = extra_fields
+                    .iter()
+                    .map(|field| format!("{}:{}={}", field.name, field.bits, field.value))
+                    .collect();
+            }
+        }
+        if args.checksum.is_none() {
+            if let Some(checksum) = self.checksum {
+                args.checksum = Some(checksum.algorithm.to_string());
+                if args.checksum_bits.is_none() {
+                    args.checksum_bits = Some(checksum.bits);
+                }
+            }
+        }
+        if args.layout_version.is_none() {
+            if let Some(layout_version) = self.layout_version {
+                args.layout_version = Some(layout_version.version);
+                if args.layout_version_bits.is_none() {
+                    args.layout_version_bits = Some(layout_version.bits);
+                }
+            }
+        }
+    }
+}
+
+fn bits<'de, D: Deserializer<'de>, const MIN: u8, const MAX: u8>(
+    deserializer: D,
+) -> Result<Option<u8>, D::Error> {
+    let value = u8::deserialize(deserializer)?;
+    if value < MIN || value > MAX {
+        return Err(D::Error::custom(format!(
+            "value '{}' out of range, minimum {} and maximum {}",
+            value, MIN, MAX
+        )));
+    }
+    Ok(Some(value))
+}
+
+fn custom_epoch<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    OffsetDateTime::parse(&value, &Rfc3339).map_err(|_| {
+        D::Error::custom(format!(
+            "could not parse custom_epoch '{}' as an RFC-3339/ISO-8601 datetime",
+            value
+        ))
+    })?;
+    Ok(Some(value))
+}
+
+/// Field order as a list of names or as a comma separated string.
+fn field_order<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum FieldOrder {
+        List(Vec<String>),
+        Text(String),
+    }
+    Ok(Some(match FieldOrder::deserialize(deserializer)? {
+        FieldOrder::List(names) => names.join(","),
+        FieldOrder::Text(names) => names,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parse_errors() {
+        use super::*;
+        let config = ConfigFile::parse(
+            "preset = \"twitter\"\nfield_order = \"node_id,sequence\"\n[checksum]\nalgorithm = \"crc\"\nbits = 4\n",
+            false,
+        )
+        .unwrap();
+        assert_eq!(config.preset.as_deref(), Some("twitter"));
+        assert_eq!(config.checksum.unwrap().bits, 4);
+        let config =
+            ConfigFile::parse(r#"{"field_order": ["node_id", "sequence"]}"#, true).unwrap();
+        assert_eq!(config.field_order.as_deref(), Some("node_id,sequence"));
+        let error = ConfigFile::parse("node_id = 1\nsequence_bits = 17\n", false).unwrap_err();
+        assert!(error.contains("line 2"), "{}", error);
+        let error = ConfigFile::parse(
+            "{\n\"unused_bits\": 1,\n\"custom_epoch\": \"2020\"\n}",
+            true,
+        )
+        .unwrap_err();
+        assert!(
+            error.contains("custom_epoch") && error.contains("line"),
+            "{}",
+            error
+        );
+        assert!(ConfigFile::parse("sequence_bit = 10\n", false).is_err());
+    }
+}
@@ -0,0 +1,59 @@
+//! `coordinator` subcommand, handing out node IDs to the generators of a
+//! fleet started with `--coordinator`.
+use super::error::CliError;
+use ::sequence_generator::coordinator::Coordinator;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(clap::Args, Debug)]
+pub struct CoordinatorOpt {
+    #[clap(
+        long = "--listen",
+        default_value = "127.0.0.1:7070",
+        help = "Address and port to listen on."
+    )]
+    listen: String,
+    #[clap(
+        short = 'w',
+        long = "--node-id-bits",
+        default_value = "9",
+        help = "Bits of the node IDs handed out. Clients with fewer node ID bits only get node IDs that fit in them. Maximum: 16. Minimum: 1"
+    )]
+    node_id_bits: u8,
+    #[clap(
+        long = "--lease-ttl-ms",
+        default_value = "10000",
+        help = "Milliseconds a client keeps its node ID without a heartbeat. Clients send one every third of it."
+    )]
+    lease_ttl_ms: u64,
+    #[clap(
+        long = "--reissue-delay-ms",
+        default_value = "30000",
+        help = "Milliseconds after the last heartbeat before a node ID is handed out again, longer than the lease time to live. No node ID is handed out for as long after startup."
+    )]
+    reissue_delay_ms: u64,
+}
+
+pub fn run(options: CoordinatorOpt) -> Result<(), CliError> {
+    let coordinator = Coordinator::try_new(
+        options.node_id_bits,
+        Duration::from_millis(options.lease_ttl_ms),
+        Duration::from_millis(options.reissue_delay_ms),
+    )
+    .map_err(CliError::usage)?;
+    let listener = TcpListener::bind(&options.listen).map_err(|error| {
+        CliError::io(format!(
+            "Could not listen on '{}': {}",
+            options.listen, error
+        ))
+    })?;
+    eprintln!(
+        "Coordinator listening on '{}' for {} node IDs",
+        options.listen,
+        1_u32 << options.node_id_bits
+    );
+    Arc::new(coordinator)
+        .serve(listener)
+        .map_err(|error| CliError::io(format!("Coordinator stopped: {}", error)))
+}
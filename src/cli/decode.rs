@@ -0,0 +1,188 @@
+//! `decode` subcommand, printing the fields of IDs generated with the
+//! configured layout.
+use super::error::CliError;
+use super::generator::{decode_id, DecodedId};
+use ::sequence_generator::layout::FieldKind;
+use ::sequence_generator::sequence_generator::SequenceProperties;
+use serde_json::{json, Map, Value};
+use std::io::{self, BufRead};
+use time::UtcOffset;
+
+#[derive(clap::Args, Debug)]
+pub struct DecodeOpt {
+    #[clap(help = "IDs to decode. Read from stdin, one per line, when none is given.")]
+    ids: Vec<String>,
+    #[clap(
+        long = "--offset",
+        default_value = "Z",
+        help = "UTC offset of the printed timestamps, 'Z' or '+HH:MM'/'-HH:MM'."
+    )]
+    offset: String,
+    #[clap(
+        long = "--json",
+        help = "Print a JSON object per ID, one per line, instead of 'name: value' lines."
+    )]
+    json: bool,
+}
+
+pub fn run(properties: SequenceProperties, options: DecodeOpt) -> Result<(), CliError> {
+    let offset = parse_offset(&options.offset).ok_or_else(|| {
+        CliError::usage(format!(
+            "Invalid UTC offset '{}', expected 'Z' or '+HH:MM'.",
+            options.offset
+        ))
+    })?;
+    let mut failed = false;
+    let mut first = true;
+    let mut decode = |id: &str| {
+        let decoded = id
+            .parse::<u64>()
+            .map_err(|_| format!("invalid ID '{}'", id))
+            .and_then(|id| decode_id(id, &properties, offset));
+        match decoded {
+            Ok(decoded) if options.json => println!("{}", to_json(&decoded)),
+            Ok(decoded) => {
+                if !first {
+                    println!();
+                }
+                print!("{}", to_text(&decoded, &properties));
+            }
+            Err(error) => {
+                eprintln!("ERROR: {}", error);
+                failed = true;
+            }
+        }
+        first = false;
+    };
+    if options.ids.is_empty() {
+        for line in io::stdin().lock().lines() {
+            let line =
+                line.map_err(|error| CliError::io(format!("Could not read stdin: {}", error)))?;
+            if !line.trim().is_empty() {
+                decode(line.trim());
+            }
+        }
+    } else {
+        for id in &options.ids {
+            decode(id);
+        }
+    }
+    if failed {
+        return Err(CliError::InvalidIds);
+    }
+    Ok(())
+}
+
+/// `Z`, `+HH:MM` or `-HH:MM`.
+fn parse_offset(value: &str) -> Option<UtcOffset> {
+    if value.eq_ignore_ascii_case("z") {
+        return Some(UtcOffset::UTC);
+    }
+    let (sign, rest) = match value.as_bytes().first()? {
+        b'+' => (1, &value[1..]),
+        b'-' => (-1, &value[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = rest.split_once(':')?;
+    if hours.len() != 2 || minutes.len() != 2 {
+        return None;
+    }
+    let hours = hours.parse::<i8>().ok()?;
+    let minutes = minutes.parse::<i8>().ok()?;
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()
+}
+
+/// Every field of a decoded ID, with the extra fields, checksum and layout
+/// version under `fields` and the node subfields under `node_subfields`.
+pub fn to_json(decoded: &DecodedId) -> Value {
+    let mut value = json!({
+        "id": decoded.id,
+        "timestamp": decoded.timestamp,
+        "unix_micros": decoded.unix_micros,
+        "ticks": decoded.ticks,
+        "node_id": decoded.node_id,
+        "sequence": decoded.sequence,
+        "unused": decoded.unused,
+    });
+    let fields: Map<String, Value> = other_fields(decoded)
+        .map(|(name, value)| (name.clone(), json!(value)))
+        .collect();
+    if !fields.is_empty() {
+        value["fields"] = Value::Object(fields);
+    }
+    if !decoded.node_subfields.is_empty() {
+        let subfields: Map<String, Value> = decoded
+            .node_subfields
+            .iter()
+            .map(|(name, value)| (name.clone(), json!(value)))
+            .collect();
+        value["node_subfields"] = Value::Object(subfields);
+    }
+    value
+}
+
+fn to_text(decoded: &DecodedId, properties: &SequenceProperties) -> String {
+    let mut text = format!(
+        "id: {}\ntimestamp: {}\nticks: {} (x{} us since {})\nnode_id: {}\nsequence: {}\nunused: {}\n",
+        decoded.id,
+        decoded.timestamp,
+        decoded.ticks,
+        10_u64.pow(properties.micros_ten_power.into()),
+        time::OffsetDateTime::from(properties.custom_epoch)
+            .format(&time::format_description::well_known::Rfc3339)
+            .unwrap_or_default(),
+        decoded.node_id,
+        decoded.sequence,
+        decoded.unused,
+    );
+    for (name, value) in &decoded.node_subfields {
+        text.push_str(&format!("node_id.{}: {}\n", name, value));
+    }
+    for (name, value) in other_fields(decoded) {
+        text.push_str(&format!("{}: {}\n", name, value));
+    }
+    text
+}
+
+/// Fields besides the timestamp, node ID and sequence.
+pub fn other_fields(decoded: &DecodedId) -> impl Iterator<Item = &(String, u64)> {
+    decoded.fields.iter().filter(|(name, _)| {
+        !matches!(
+            FieldKind::from_name(name),
+            FieldKind::Timestamp | FieldKind::NodeId | FieldKind::Sequence
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn decode_with_offset() {
+        use super::*;
+        use ::sequence_generator::sequence_generator::generate_id;
+        use std::time::{Duration, UNIX_EPOCH};
+        assert_eq!(parse_offset("Z"), Some(UtcOffset::UTC));
+        assert_eq!(
+            parse_offset("-05:30"),
+            Some(UtcOffset::from_hms(-5, -30, 0).unwrap())
+        );
+        assert_eq!(parse_offset("+5"), None);
+        // 2020-01-01T00:00:00Z
+        let custom_epoch = UNIX_EPOCH + Duration::from_secs(1_577_836_800);
+        let mut properties = SequenceProperties::new(custom_epoch, 9, 7, 11, 2, 0, 1500);
+        properties.add_field("type", 4);
+        properties.set_field_value("type", 3);
+        let id = generate_id(&properties).unwrap();
+        let decoded = decode_id(id, &properties, parse_offset("+02:00").unwrap()).unwrap();
+        assert!(decoded.timestamp.ends_with("+02:00"));
+        let value = to_json(&decoded);
+        assert_eq!(value["node_id"], 7);
+        assert_eq!(value["unused"], 0);
+        assert_eq!(value["fields"]["type"], 3);
+        assert_eq!(value["ticks"], decoded.ticks);
+        let text = to_text(&decoded, &properties);
+        assert!(text.contains("node_id: 7\n"));
+        assert!(text.contains("ticks: ") && text.contains("(x100 us since 2020-01-01T00:00:00Z)"));
+        assert!(text.ends_with("type: 3\n"));
+    }
+}
@@ -0,0 +1,134 @@
+//! Errors of the binary, printed on stderr with an exit status per kind.
+use ::sequence_generator::sequence_generator::SequenceGeneratorError;
+use std::fmt;
+
+/// Exit status when some IDs given to `decode` could not be decoded.
+pub const EXIT_INVALID_IDS: i32 = 1;
+/// Exit status of conflicting or malformed command line parameters, as for
+/// the usage errors reported by the argument parser.
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_CONFIG: i32 = 3;
+pub const EXIT_CLOCK: i32 = 4;
+pub const EXIT_IO: i32 = 5;
+
+#[derive(Debug)]
+pub enum CliError {
+    /// Some IDs could not be decoded, each already reported.
+    InvalidIds,
+    Usage(String),
+    /// Invalid layout, node ID or other configuration, from any source.
+    Config(String),
+    /// The system clock is before the custom epoch or behind the persisted
+    /// high-water mark.
+    Clock(String),
+    /// Reading or writing a file, stdin or stdout, or a network connection
+    /// failed.
+    Io(String),
+}
+
+impl CliError {
+    pub fn usage(message: impl ToString) -> Self {
+        CliError::Usage(strip_prefix(message))
+    }
+    pub fn config(message: impl ToString) -> Self {
+        CliError::Config(strip_prefix(message))
+    }
+    pub fn io(message: impl ToString) -> Self {
+        CliError::Io(strip_prefix(message))
+    }
+    /// Error of the generator, classified by its cause, after `context`.
+    pub fn generator(context: &str, error: SequenceGeneratorError) -> Self {
+        let message = format!("{}: {}", context, error);
+        match error {
+            SequenceGeneratorError::SystemTime(_)
+            | SequenceGeneratorError::ClockBehindHighWaterMark { .. } => CliError::Clock(message),
+            SequenceGeneratorError::Io(_) | SequenceGeneratorError::NodeIdLeaseLost(_) => {
+                CliError::Io(message)
+            }
+            _ => CliError::Config(message),
+        }
+    }
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::InvalidIds => EXIT_INVALID_IDS,
+            CliError::Usage(_) => EXIT_USAGE,
+            CliError::Config(_) => EXIT_CONFIG,
+            CliError::Clock(_) => EXIT_CLOCK,
+            CliError::Io(_) => EXIT_IO,
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::InvalidIds => f.write_str("Some IDs could not be decoded"),
+            CliError::Usage(message)
+            | CliError::Config(message)
+            | CliError::Clock(message)
+            | CliError::Io(message) => f.write_str(message),
+        }
+    }
+}
+
+/// Messages of the library and of the parsers of the binary start with
+/// "ERROR: ", which is printed once for every error.
+fn strip_prefix(message: impl ToString) -> String {
+    let message = message.to_string();
+    match message.strip_prefix("ERROR: ") {
+        Some(message) => message.to_owned(),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn exit_codes() {
+        use super::*;
+        use ::sequence_generator::sequence_generator::SequenceProperties;
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+        let error = CliError::config(
+            SequenceProperties::try_new(UNIX_EPOCH, 9, 0, 0, 2, 0, 1500).unwrap_err(),
+        );
+        assert_eq!(error.exit_code(), EXIT_CONFIG);
+        assert_eq!(
+            error.to_string(),
+            "sequence_bits '0' must be larger or equal than 1."
+        );
+        let error = CliError::config(
+            SequenceProperties::try_new(UNIX_EPOCH, 9, 0, 11, 30, 0, 1500).unwrap_err(),
+        );
+        assert_eq!(
+            error.to_string(),
+            "micros_ten_power '30' is larger than the maximum value of 18."
+        );
+        let future = SequenceProperties::new(
+            SystemTime::now() + Duration::from_secs(3600),
+            9,
+            0,
+            11,
+            2,
+            0,
+            1500,
+        );
+        let clock = CliError::generator(
+            "Failed to generate an ID",
+            ::sequence_generator::sequence_generator::generate_id(&future).unwrap_err(),
+        );
+        assert_eq!(clock.exit_code(), EXIT_CLOCK);
+        let behind = CliError::generator(
+            "State file 'state'",
+            SequenceGeneratorError::ClockBehindHighWaterMark {
+                behind: Duration::from_secs(1),
+            },
+        );
+        assert_eq!(behind.exit_code(), EXIT_CLOCK);
+        assert!(behind.to_string().starts_with("State file 'state': "));
+        assert_eq!(
+            CliError::usage("ERROR: Conflicting").to_string(),
+            "Conflicting"
+        );
+        assert_eq!(CliError::io("Broken").exit_code(), EXIT_IO);
+    }
+}
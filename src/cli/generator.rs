@@ -0,0 +1,114 @@
+//! Single long-lived generator shared by the connections of every server
+//! mode of the `serve` subcommand.
+use ::sequence_generator::layout::FieldKind;
+use ::sequence_generator::metrics::GeneratorMetrics;
+use ::sequence_generator::sequence_generator::{self, SequenceGeneratorError, SequenceProperties};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, UNIX_EPOCH};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, UtcOffset};
+
+#[derive(Debug)]
+pub struct SharedGenerator {
+    properties: Mutex<SequenceProperties>,
+    metrics: Option<Arc<GeneratorMetrics>>,
+}
+
+/// Fields of an ID, with its timestamp in RFC-3339 and in microseconds since
+/// the UNIX epoch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedId {
+    pub id: u64,
+    pub timestamp: String,
+    pub unix_micros: u64,
+    /// Timestamp field as stored, in ticks since the custom epoch.
+    pub ticks: u64,
+    /// Value of the unused left-most bits, 0 unless the ID was altered.
+    pub unused: u64,
+    pub node_id: u16,
+    pub sequence: u16,
+    pub fields: Vec<(String, u64)>,
+    pub node_subfields: Vec<(String, u16)>,
+}
+
+impl SharedGenerator {
+    pub fn new(properties: SequenceProperties) -> Self {
+        SharedGenerator {
+            metrics: properties.metrics().cloned(),
+            properties: Mutex::new(properties),
+        }
+    }
+    /// Metrics attached to the properties, readable without waiting for
+    /// the generator.
+    pub fn metrics(&self) -> Option<&GeneratorMetrics> {
+        self.metrics.as_deref()
+    }
+    /// The generator, also after a request panicked while holding it, so
+    /// that one failed request does not fail every later one.
+    pub fn properties(&self) -> MutexGuard<'_, SequenceProperties> {
+        self.properties
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+    /// `count` consecutive IDs, generated while holding the generator.
+    pub fn generate(&self, count: usize) -> Result<Vec<u64>, SequenceGeneratorError> {
+        let properties = self.properties();
+        (0..count)
+            .map(|_| sequence_generator::generate_id(&properties))
+            .collect()
+    }
+    pub fn decode(&self, id: u64) -> Result<DecodedId, String> {
+        decode_id(id, &self.properties(), UtcOffset::UTC)
+    }
+    /// Node ID IDs are generated with, or why they can no longer be.
+    pub fn health(&self) -> Result<u16, String> {
+        let properties = self.properties();
+        match properties.coordinator_lease() {
+            Some(lease) if !lease.is_valid() => Err(format!(
+                "lease of node ID {} from the coordinator lost",
+                lease.node_id()
+            )),
+            _ => Ok(properties.node_id),
+        }
+    }
+}
+
+/// Fields of an ID generated with `properties`, after validating its
+/// checksum, with its timestamp formatted in the given UTC offset.
+pub fn decode_id(
+    id: u64,
+    properties: &SequenceProperties,
+    offset: UtcOffset,
+) -> Result<DecodedId, String> {
+    sequence_generator::validate_id(id, properties).map_err(|error| error.to_string())?;
+    let time = properties
+        .custom_epoch
+        .checked_add(Duration::from_micros(
+            sequence_generator::decode_timestamp_micros(id, properties),
+        ))
+        .ok_or_else(|| format!("timestamp of ID '{}' is out of range", id))?;
+    let timestamp = OffsetDateTime::from(time)
+        .to_offset(offset)
+        .format(&Rfc3339)
+        .map_err(|error| error.to_string())?;
+    let unused_bits = properties.layout().unused_bits();
+    Ok(DecodedId {
+        id,
+        timestamp,
+        unix_micros: time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_micros() as u64),
+        ticks: properties
+            .layout()
+            .field(FieldKind::Timestamp)
+            .map_or(0, |field| field.decode(id)),
+        unused: if unused_bits == 0 {
+            0
+        } else {
+            id >> (64 - unused_bits)
+        },
+        node_id: sequence_generator::decode_node_id(id, properties),
+        sequence: sequence_generator::decode_sequence_id(id, properties),
+        fields: sequence_generator::decode_fields(id, properties),
+        node_subfields: sequence_generator::decode_node_subfields(id, properties),
+    })
+}
@@ -0,0 +1,290 @@
+//! gRPC server mode of the `serve` subcommand, implementing the service of
+//! `proto/sequence_generator.proto`.
+use super::generator::SharedGenerator;
+use ::sequence_generator::layout::Field;
+use ::sequence_generator::sequence_generator::SequenceProperties;
+use std::error::Error;
+use std::net::TcpListener;
+use std::sync::Arc;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("sequence_generator.v1");
+}
+
+use proto::sequence_generator_server::{SequenceGenerator, SequenceGeneratorServer};
+use proto::{
+    DecodeRequest, DecodeResponse, FieldLayout, FieldValue, GenerateIdRequest, GenerateIdResponse,
+    GenerateIdsRequest, GenerateIdsResponse, GetLayoutRequest, GetLayoutResponse, StreamIdsRequest,
+};
+
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct Server {
+    generator: Arc<SharedGenerator>,
+    max_count: usize,
+}
+
+impl Server {
+    pub fn new(generator: Arc<SharedGenerator>, max_count: usize) -> Self {
+        Server {
+            generator,
+            max_count,
+        }
+    }
+    /// Answer the clients of `listener` until the server fails.
+    pub fn serve(self, listener: TcpListener) -> Result<(), Box<dyn Error>> {
+        let runtime = tokio::runtime::Runtime::new()?;
+        runtime.block_on(async move {
+            listener.set_nonblocking(true)?;
+            let listener = tokio::net::TcpListener::from_std(listener)?;
+            tonic::transport::Server::builder()
+                .add_service(SequenceGeneratorServer::new(self))
+                .serve_with_incoming(TcpListenerStream::new(listener))
+                .await?;
+            Ok(())
+        })
+    }
+    /// Run `task` with the generator on a blocking thread, since its lock
+    /// is held while the generator waits for the next tick or for another
+    /// request.
+    async fn with_generator<T: Send + 'static>(
+        &self,
+        task: impl FnOnce(&SharedGenerator) -> T + Send + 'static,
+    ) -> Result<T, Status> {
+        let generator = Arc::clone(&self.generator);
+        tokio::task::spawn_blocking(move || task(&generator))
+            .await
+            .map_err(|error| Status::internal(error.to_string()))
+    }
+    async fn generate(&self, count: usize) -> Result<Vec<u64>, Status> {
+        if count == 0 || count > self.max_count {
+            return Err(Status::invalid_argument(format!(
+                "count '{}' must be between 1 and {}",
+                count, self.max_count
+            )));
+        }
+        self.with_generator(move |generator| generator.generate(count))
+            .await?
+            .map_err(|error| Status::unavailable(error.to_string()))
+    }
+}
+
+#[tonic::async_trait]
+impl SequenceGenerator for Server {
+    async fn generate_id(
+        &self,
+        _request: Request<GenerateIdRequest>,
+    ) -> Result<Response<GenerateIdResponse>, Status> {
+        let ids = self.generate(1).await?;
+        Ok(Response::new(GenerateIdResponse { id: ids[0] }))
+    }
+    async fn generate_ids(
+        &self,
+        request: Request<GenerateIdsRequest>,
+    ) -> Result<Response<GenerateIdsResponse>, Status> {
+        let ids = self.generate(request.into_inner().count as usize).await?;
+        Ok(Response::new(GenerateIdsResponse { ids }))
+    }
+
+    type StreamIdsStream = ReceiverStream<Result<GenerateIdsResponse, Status>>;
+
+    async fn stream_ids(
+        &self,
+        request: Request<StreamIdsRequest>,
+    ) -> Result<Response<Self::StreamIdsStream>, Status> {
+        let request = request.into_inner();
+        let batch_size = match request.batch_size as usize {
+            0 => DEFAULT_BATCH_SIZE.min(self.max_count),
+            batch_size if batch_size <= self.max_count => batch_size,
+            batch_size => {
+                return Err(Status::invalid_argument(format!(
+                    "batch size '{}' must be at most {}",
+                    batch_size, self.max_count
+                )))
+            }
+        };
+        let mut remaining = match request.count {
+            0 => None,
+            count => Some(count),
+        };
+        // A single batch in flight: the next one is generated once the
+        // client has taken the previous one
+        let (sender, receiver) = mpsc::channel(1);
+        let generator = Arc::clone(&self.generator);
+        tokio::task::spawn_blocking(move || loop {
+            let count = remaining.map_or(batch_size, |remaining| {
+                remaining.min(batch_size as u64) as usize
+            });
+            if count == 0 {
+                return;
+            }
+            let batch = generator
+                .generate(count)
+                .map(|ids| GenerateIdsResponse { ids })
+                .map_err(|error| Status::unavailable(error.to_string()));
+            let failed = batch.is_err();
+            // Stop once the client is gone
+            if sender.blocking_send(batch).is_err() || failed {
+                return;
+            }
+            remaining = remaining.map(|remaining| remaining - count as u64);
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+    async fn decode(
+        &self,
+        request: Request<DecodeRequest>,
+    ) -> Result<Response<DecodeResponse>, Status> {
+        let id = request.into_inner().id;
+        let decoded = self
+            .with_generator(move |generator| generator.decode(id))
+            .await?
+            .map_err(Status::invalid_argument)?;
+        Ok(Response::new(DecodeResponse {
+            id: decoded.id,
+            timestamp: decoded.timestamp,
+            unix_micros: decoded.unix_micros,
+            node_id: decoded.node_id.into(),
+            sequence: decoded.sequence.into(),
+            fields: decoded
+                .fields
+                .into_iter()
+                .map(|(name, value)| FieldValue { name, value })
+                .collect(),
+            node_subfields: decoded
+                .node_subfields
+                .into_iter()
+                .map(|(name, value)| FieldValue {
+                    name,
+                    value: value.into(),
+                })
+                .collect(),
+        }))
+    }
+    async fn get_layout(
+        &self,
+        _request: Request<GetLayoutRequest>,
+    ) -> Result<Response<GetLayoutResponse>, Status> {
+        self.with_generator(|generator| layout_response(&generator.properties()))
+            .await?
+            .map(Response::new)
+            .map_err(Status::internal)
+    }
+}
+
+fn layout_response(properties: &SequenceProperties) -> Result<GetLayoutResponse, String> {
+    let field_layout = |field: &Field| FieldLayout {
+        name: field.name.clone(),
+        bits: field.bits.into(),
+        shift: field.shift.into(),
+    };
+    Ok(GetLayoutResponse {
+        custom_epoch: OffsetDateTime::from(properties.custom_epoch)
+            .format(&Rfc3339)
+            .map_err(|error| error.to_string())?,
+        micros_ten_power: properties.micros_ten_power.into(),
+        unused_bits: properties.layout().unused_bits().into(),
+        fields: properties
+            .layout()
+            .fields()
+            .iter()
+            .map(field_layout)
+            .collect(),
+        node_subfields: properties
+            .node_id_layout()
+            .map(|node_id_layout| {
+                node_id_layout
+                    .subfields()
+                    .iter()
+                    .map(|subfield| FieldLayout {
+                        name: subfield.name.clone(),
+                        bits: subfield.bits.into(),
+                        shift: subfield.shift.into(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        node_id: properties.node_id.into(),
+        layout_version: properties.layout_version(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn serve_over_grpc() {
+        use super::proto::sequence_generator_client::SequenceGeneratorClient;
+        use super::*;
+        use std::time::{Duration, UNIX_EPOCH};
+        use tokio_stream::StreamExt;
+        let properties = SequenceProperties::new(
+            UNIX_EPOCH + Duration::from_secs(1_577_836_800),
+            9,
+            5,
+            11,
+            3,
+            0,
+            1000,
+        );
+        let generator = Arc::new(SharedGenerator::new(properties));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Server::new(generator, 100);
+        std::thread::spawn(move || server.serve(listener).unwrap());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let channel = tonic::transport::Channel::from_shared(format!("http://{}", address))
+                .unwrap()
+                .connect()
+                .await
+                .unwrap();
+            let mut client = SequenceGeneratorClient::new(channel);
+            let ids = client
+                .generate_ids(GenerateIdsRequest { count: 3 })
+                .await
+                .unwrap()
+                .into_inner()
+                .ids;
+            assert_eq!(ids.len(), 3);
+            let status = client
+                .generate_ids(GenerateIdsRequest { count: 101 })
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            let decoded = client
+                .decode(DecodeRequest { id: ids[0] })
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(decoded.node_id, 5);
+            let mut stream = client
+                .stream_ids(StreamIdsRequest {
+                    count: 250,
+                    batch_size: 100,
+                })
+                .await
+                .unwrap()
+                .into_inner();
+            let mut streamed = Vec::new();
+            while let Some(batch) = stream.next().await {
+                streamed.extend(batch.unwrap().ids);
+            }
+            assert_eq!(streamed.len(), 250);
+            assert!(streamed.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(streamed[0] > ids[2]);
+            let layout = client
+                .get_layout(GetLayoutRequest {})
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(layout.custom_epoch, "2020-01-01T00:00:00Z");
+            let bits: Vec<u32> = layout.fields.iter().map(|field| field.bits).collect();
+            assert_eq!(bits, vec![44, 11, 9]);
+        });
+    }
+}
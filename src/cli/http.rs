@@ -0,0 +1,423 @@
+//! HTTP/1.1 server mode of the `serve` subcommand.
+//!
+//! - `GET /id`: one ID
+//! - `GET /ids?n=<count>`: `count` IDs, at most `--max-count`
+//! - `GET /decode/<id>`: timestamp, node ID, sequence and every field of an ID
+//! - `GET /health`: `503 Service Unavailable` once IDs can no longer be
+//!   generated, e.g. after losing the node ID lease of a coordinator
+//! - `GET /metrics`: metrics of the generator in the Prometheus text format
+//!
+//! Responses are JSON, or plain text with `?format=text`, an `Accept:
+//! text/plain` header or `--format text`.
+use super::generator::SharedGenerator;
+use super::serve::ConnectionLimit;
+use super::serve::ResponseFormat;
+use serde_json::{json, Map, Value};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Largest request line or header, including its line ending.
+const MAX_LINE_BYTES: u64 = 8192;
+
+#[derive(Debug)]
+pub struct Server {
+    generator: Arc<SharedGenerator>,
+    max_count: usize,
+    connections: Arc<ConnectionLimit>,
+    format: ResponseFormat,
+}
+
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    body: Body,
+}
+
+#[derive(Debug)]
+enum Body {
+    /// Rendered as JSON or plain text, as the client asked
+    Value(Value),
+    /// Prometheus text exposition format
+    Metrics(String),
+}
+
+impl Server {
+    pub fn new(
+        generator: Arc<SharedGenerator>,
+        max_count: usize,
+        format: ResponseFormat,
+        connections: Arc<ConnectionLimit>,
+    ) -> Self {
+        Server {
+            generator,
+            max_count,
+            connections,
+            format,
+        }
+    }
+    /// Answer the clients of `listener`, one thread per connection.
+    pub fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    super::serve::accept_failed("HTTP", error);
+                    continue;
+                }
+            };
+            let connection = match self.connections.acquire() {
+                Some(connection) => connection,
+                // Closed by dropping the stream
+                None => {
+                    super::serve::connection_refused("HTTP");
+                    continue;
+                }
+            };
+            let server = Arc::clone(&self);
+            thread::spawn(move || {
+                // A client disconnecting abruptly only ends its connection
+                let _ = server.handle(stream);
+                drop(connection);
+            });
+        }
+        Ok(())
+    }
+    /// Requests of a keep-alive connection, until the client closes it.
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(60)))?;
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        loop {
+            let mut request_line = String::new();
+            if read_line(&mut reader, &mut request_line)? == 0 {
+                return Ok(());
+            }
+            if !request_line.ends_with('\n') {
+                let response = error(414, "request line too long");
+                return write_response(&mut writer, &response, self.format, false, true);
+            }
+            let request: Vec<&str> = request_line.split_whitespace().collect();
+            let (method, target, version) = match request[..] {
+                [method, target, version] => (method, target, version),
+                _ => {
+                    let response = error(400, "malformed request line");
+                    return write_response(&mut writer, &response, self.format, false, true);
+                }
+            };
+            let mut accept = None;
+            let mut content_length = 0_u64;
+            let mut close = version == "HTTP/1.0";
+            loop {
+                let mut header = String::new();
+                if read_line(&mut reader, &mut header)? == 0 {
+                    return Ok(());
+                }
+                if !header.ends_with('\n') {
+                    let response = error(431, "header too long");
+                    return write_response(&mut writer, &response, self.format, false, true);
+                }
+                let header = header.trim_end();
+                if header.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    let value = value.trim();
+                    match name.trim().to_ascii_lowercase().as_str() {
+                        "accept" => accept = Some(value.to_ascii_lowercase()),
+                        "content-length" => content_length = value.parse().unwrap_or(0),
+                        "connection" => close = value.eq_ignore_ascii_case("close"),
+                        _ => {}
+                    }
+                }
+            }
+            // Requests have no body, skip any sent anyway
+            io::copy(&mut reader.by_ref().take(content_length), &mut io::sink())?;
+            let (path, query) = target.split_once('?').unwrap_or((target, ""));
+            let format = response_format(query, accept.as_deref()).unwrap_or(self.format);
+            let response = match method {
+                "GET" | "HEAD" => self.respond(path, query),
+                _ => error(405, &format!("method '{}' not allowed", method)),
+            };
+            write_response(&mut writer, &response, format, method == "HEAD", close)?;
+            if close {
+                return Ok(());
+            }
+        }
+    }
+    fn respond(&self, path: &str, query: &str) -> Response {
+        if path == "/metrics" {
+            if let Some(metrics) = self.generator.metrics() {
+                return Response {
+                    status: 200,
+                    body: Body::Metrics(metrics.render()),
+                };
+            }
+        }
+        let result = match path.trim_end_matches('/') {
+            "/id" => self.generate(1).map(|ids| json!({ "id": ids[0] })),
+            "/ids" => query_parameter(query, "n")
+                .map_or(Ok(1), |count| {
+                    count
+                        .parse::<usize>()
+                        .map_err(|_| (400, format!("invalid count '{}'", count)))
+                })
+                .and_then(|count| self.generate(count))
+                .map(|ids| json!({ "ids": ids })),
+            "/health" => self.health(),
+            path => match path.strip_prefix("/decode/") {
+                Some(id) => self.decode(id),
+                None => Err((404, format!("no endpoint at '{}'", path))),
+            },
+        };
+        match result {
+            Ok(body) => Response {
+                status: 200,
+                body: Body::Value(body),
+            },
+            Err((status, message)) => error(status, &message),
+        }
+    }
+    fn generate(&self, count: usize) -> Result<Vec<u64>, (u16, String)> {
+        if count == 0 || count > self.max_count {
+            return Err((
+                400,
+                format!("count '{}' must be between 1 and {}", count, self.max_count),
+            ));
+        }
+        self.generator
+            .generate(count)
+            .map_err(|error| (503, error.to_string()))
+    }
+    fn decode(&self, id: &str) -> Result<Value, (u16, String)> {
+        let id = id
+            .parse::<u64>()
+            .map_err(|_| (400, format!("invalid ID '{}'", id)))?;
+        let decoded = self.generator.decode(id).map_err(|error| (400, error))?;
+        let fields: Map<String, Value> = decoded
+            .fields
+            .into_iter()
+            .map(|(name, value)| (name, json!(value)))
+            .collect();
+        let mut body = json!({
+            "id": id,
+            "timestamp": decoded.timestamp,
+            "node_id": decoded.node_id,
+            "sequence": decoded.sequence,
+            "fields": fields,
+        });
+        if !decoded.node_subfields.is_empty() {
+            let subfields: Map<String, Value> = decoded
+                .node_subfields
+                .into_iter()
+                .map(|(name, value)| (name, json!(value)))
+                .collect();
+            body["node_subfields"] = Value::Object(subfields);
+        }
+        Ok(body)
+    }
+    fn health(&self) -> Result<Value, (u16, String)> {
+        self.generator
+            .health()
+            .map(|node_id| json!({ "status": "ok", "node_id": node_id }))
+            .map_err(|error| (503, error))
+    }
+}
+
+fn error(status: u16, message: &str) -> Response {
+    Response {
+        status,
+        body: Body::Value(json!({ "error": message })),
+    }
+}
+
+/// Line of at most `MAX_LINE_BYTES`, which only ends without a line ending
+/// when longer or at the end of the stream.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> io::Result<usize> {
+    reader.take(MAX_LINE_BYTES).read_line(line)
+}
+
+fn query_parameter<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Format asked for by the `format` query parameter, else the `Accept`
+/// header.
+fn response_format(query: &str, accept: Option<&str>) -> Option<ResponseFormat> {
+    if let Some(format) = query_parameter(query, "format") {
+        return format.parse().ok();
+    }
+    let accept = accept?;
+    if accept.contains("application/json") {
+        Some(ResponseFormat::Json)
+    } else if accept.contains("text/plain") {
+        Some(ResponseFormat::Text)
+    } else {
+        None
+    }
+}
+
+/// Plain text body: one line per value, `name: value` for objects of
+/// several entries, e.g. just the IDs for `/ids`.
+fn to_text(body: &Value) -> String {
+    match body {
+        Value::Object(entries) if entries.len() == 1 => entries.values().map(to_text).collect(),
+        Value::Object(entries) => entries
+            .iter()
+            .map(|(name, value)| match value {
+                Value::Array(_) => to_text(value),
+                Value::Object(_) => to_text(value)
+                    .lines()
+                    .map(|line| format!("{}.{}\n", name, line))
+                    .collect(),
+                value => format!("{}: {}\n", name, to_text(value).trim_end()),
+            })
+            .collect(),
+        Value::Array(values) => values.iter().map(to_text).collect(),
+        Value::String(value) => format!("{}\n", value),
+        value => format!("{}\n", value),
+    }
+}
+
+fn write_response(
+    writer: &mut impl Write,
+    response: &Response,
+    format: ResponseFormat,
+    head: bool,
+    close: bool,
+) -> io::Result<()> {
+    let (content_type, body) = match (&response.body, format) {
+        (Body::Metrics(text), _) => ("text/plain; version=0.0.4; charset=utf-8", text.clone()),
+        (Body::Value(value), ResponseFormat::Json) => ("application/json", format!("{}\n", value)),
+        (Body::Value(value), ResponseFormat::Text) => ("text/plain; charset=utf-8", to_text(value)),
+    };
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        414 => "URI Too Long",
+        431 => "Request Header Fields Too Large",
+        _ => "Service Unavailable",
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+        response.status,
+        reason,
+        content_type,
+        body.len()
+    )?;
+    if response.status == 405 {
+        write!(writer, "Allow: GET, HEAD\r\n")?;
+    }
+    if close {
+        write!(writer, "Connection: close\r\n")?;
+    }
+    write!(writer, "\r\n")?;
+    if !head {
+        writer.write_all(body.as_bytes())?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn serve_over_http() {
+        use super::*;
+        use ::sequence_generator::metrics::GeneratorMetrics;
+        use ::sequence_generator::sequence_generator::SequenceProperties;
+        use std::time::UNIX_EPOCH;
+        let mut properties = SequenceProperties::new(
+            UNIX_EPOCH + Duration::from_secs(1_577_836_800),
+            9,
+            5,
+            11,
+            3,
+            0,
+            1000,
+        );
+        properties.set_metrics(Arc::new(GeneratorMetrics::new()));
+        let generator = Arc::new(SharedGenerator::new(properties));
+        let server = Arc::new(Server::new(
+            generator,
+            100,
+            ResponseFormat::Json,
+            Arc::new(ConnectionLimit::new(8)),
+        ));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || server.serve(listener));
+        let stream = TcpStream::connect(address).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut get = |target: &str, accept: &str| {
+            write!(
+                writer,
+                "GET {} HTTP/1.1\r\nAccept: {}\r\n\r\n",
+                target, accept
+            )
+            .unwrap();
+            let mut status = String::new();
+            reader.read_line(&mut status).unwrap();
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some(value) = header.strip_prefix("Content-Length: ") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            (status, String::from_utf8(body).unwrap())
+        };
+        let (status, body) = get("/ids?n=3", "*/*");
+        assert!(status.starts_with("HTTP/1.1 200"), "{}", status);
+        let ids: Value = serde_json::from_str(&body).unwrap();
+        let ids: Vec<u64> = serde_json::from_value(ids["ids"].clone()).unwrap();
+        assert_eq!(ids.len(), 3);
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        // Same connection, plain text
+        let (_, body) = get(&format!("/decode/{}", ids[1]), "text/plain");
+        assert!(body.contains("node_id: 5\n"), "{}", body);
+        let sequence = (ids[1] >> 9) & 0x7ff;
+        assert!(
+            body.contains(&format!("sequence: {}\n", sequence)),
+            "{}",
+            body
+        );
+        let (_, body) = get("/id?format=text", "application/json");
+        assert!(body.trim().parse::<u64>().unwrap() > ids[2]);
+        assert!(get("/ids?n=101", "*/*").0.starts_with("HTTP/1.1 400"));
+        assert!(get("/health", "*/*").0.starts_with("HTTP/1.1 200"));
+        assert!(get("/unknown", "*/*").0.starts_with("HTTP/1.1 404"));
+        let (_, body) = get("/metrics", "*/*");
+        assert!(
+            body.contains("sequence_generator_ids_issued_total 4\n"),
+            "{}",
+            body
+        );
+        // Library client
+        let mut client = ::sequence_generator::client::HttpClient::new(address.to_string());
+        assert_eq!(client.next_ids(2).unwrap().len(), 2);
+        assert!(client.next_ids(101).is_err());
+        assert_eq!(client.next_ids(1).unwrap().len(), 1);
+        // Lines longer than the limit are cut instead of buffered
+        let long_line = format!("GET /{} HTTP/1.1\r\n", "a".repeat(10_000));
+        let mut line = String::new();
+        let read = read_line(&mut io::Cursor::new(long_line), &mut line).unwrap();
+        assert_eq!(read as u64, MAX_LINE_BYTES);
+        assert!(!line.ends_with('\n'));
+    }
+}
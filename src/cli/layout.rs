@@ -0,0 +1,310 @@
+//! `layout` subcommand, printing the resolved layout with its bit ranges,
+//! capacity and a bit diagram, optionally annotating the bits of an ID.
+use super::error::CliError;
+use ::sequence_generator::sequence_generator::SequenceProperties;
+use serde_json::{json, Value};
+use std::convert::TryFrom;
+use std::time::UNIX_EPOCH;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+#[derive(clap::Args, Debug)]
+pub struct LayoutOpt {
+    #[clap(help = "ID whose bits are shown field by field.")]
+    id: Option<u64>,
+    #[clap(long = "--json", help = "Print the layout as JSON.")]
+    json: bool,
+}
+
+/// Group of bits of the ID, `shift` bits from the right-most bit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Segment {
+    name: String,
+    bits: u8,
+    shift: u8,
+}
+
+impl Segment {
+    fn max_value(&self) -> u64 {
+        u64::MAX >> (64 - self.bits)
+    }
+    fn value(&self, id: u64) -> u64 {
+        (id >> self.shift) & self.max_value()
+    }
+    fn range(&self) -> String {
+        format!("{}..{}", self.shift + self.bits - 1, self.shift)
+    }
+}
+
+pub fn run(properties: SequenceProperties, options: LayoutOpt) -> Result<(), CliError> {
+    if options.json {
+        println!("{}", to_json(&properties, options.id));
+    } else {
+        print!("{}", to_text(&properties, options.id));
+    }
+    Ok(())
+}
+
+/// Unused bits and fields from the left-most to the right-most bits.
+fn segments(properties: &SequenceProperties) -> Vec<Segment> {
+    let layout = properties.layout();
+    let unused = Some(layout.unused_bits())
+        .filter(|bits| *bits > 0)
+        .map(|bits| Segment {
+            name: "unused".to_owned(),
+            bits,
+            shift: 64 - bits,
+        });
+    unused
+        .into_iter()
+        .chain(layout.fields().iter().map(|field| Segment {
+            name: field.name.clone(),
+            bits: field.bits,
+            shift: field.shift,
+        }))
+        .collect()
+}
+
+/// Node ID subfields, positioned in the ID.
+fn node_segments(properties: &SequenceProperties) -> Vec<Segment> {
+    let node_shift = properties
+        .layout()
+        .fields()
+        .iter()
+        .find(|field| field.name == "node_id")
+        .map_or(0, |field| field.shift);
+    properties
+        .node_id_layout()
+        .map(|layout| {
+            layout
+                .subfields()
+                .iter()
+                .map(|subfield| Segment {
+                    name: format!("node_id.{}", subfield.name),
+                    bits: subfield.bits,
+                    shift: node_shift + subfield.shift,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn tick_micros(properties: &SequenceProperties) -> u64 {
+    10_u64.pow(properties.micros_ten_power.into())
+}
+
+fn max_ids_per_tick(properties: &SequenceProperties) -> u64 {
+    1 << properties.sequence_bits
+}
+
+/// IDs a second at most, as a fraction for ticks longer than a second.
+fn max_ids_per_second(properties: &SequenceProperties) -> Value {
+    let ids = u128::from(max_ids_per_tick(properties)) * 1_000_000;
+    let tick_micros = u128::from(tick_micros(properties));
+    match u64::try_from(ids / tick_micros) {
+        Ok(per_second) if ids % tick_micros == 0 => json!(per_second),
+        _ => json!(ids as f64 / tick_micros as f64),
+    }
+}
+
+/// RFC-3339 time `micros` after the UNIX epoch, `None` past year 9999.
+fn format_unix_micros(micros: i128) -> Option<String> {
+    OffsetDateTime::from_unix_timestamp_nanos(micros.checked_mul(1000)?)
+        .ok()
+        .and_then(|time| time.format(&Rfc3339).ok())
+}
+
+fn epoch_micros(properties: &SequenceProperties) -> i128 {
+    properties
+        .custom_epoch
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_micros() as i128)
+}
+
+/// First time the timestamp field can no longer hold.
+fn exhaustion(properties: &SequenceProperties) -> Option<String> {
+    let timestamp = segments(properties)
+        .into_iter()
+        .find(|segment| segment.name == "timestamp")?;
+    format_unix_micros(
+        (i128::from(timestamp.max_value()) + 1)
+            .checked_mul(tick_micros(properties).into())?
+            .checked_add(epoch_micros(properties))?,
+    )
+}
+
+fn to_json(properties: &SequenceProperties, id: Option<u64>) -> Value {
+    let segment_json = |segment: &Segment| {
+        let mut value = json!({
+            "name": segment.name,
+            "bits": segment.bits,
+            "high_bit": segment.shift + segment.bits - 1,
+            "low_bit": segment.shift,
+            "max_value": segment.max_value(),
+        });
+        if let Some(id) = id {
+            value["value"] = json!(segment.value(id));
+        }
+        value
+    };
+    let mut value = json!({
+        "custom_epoch": format_unix_micros(epoch_micros(properties)),
+        "tick_micros": tick_micros(properties),
+        "max_nodes": 1_u64 << properties.node_id_bits,
+        "max_ids_per_tick": max_ids_per_tick(properties),
+        "max_ids_per_second": max_ids_per_second(properties),
+        "exhaustion": exhaustion(properties),
+        "fields": segments(properties).iter().map(segment_json).collect::<Vec<Value>>(),
+    });
+    let node_segments = node_segments(properties);
+    if !node_segments.is_empty() {
+        value["node_subfields"] = node_segments.iter().map(segment_json).collect();
+    }
+    if let Some(id) = id {
+        value["id"] = json!(id);
+        value["binary"] = json!(format!("{:064b}", id));
+    }
+    value
+}
+
+fn to_text(properties: &SequenceProperties, id: Option<u64>) -> String {
+    let segments = segments(properties);
+    let mut text = diagram(&segments, id);
+    text.push('\n');
+    let rows: Vec<Segment> = segments
+        .iter()
+        .cloned()
+        .chain(node_segments(properties))
+        .collect();
+    let width = rows
+        .iter()
+        .map(|segment| segment.name.len())
+        .max()
+        .unwrap_or(0);
+    for segment in &rows {
+        text.push_str(&format!(
+            "{:width$}  bits {:>6}  ({:>2} bit{}, max {})",
+            segment.name,
+            segment.range(),
+            segment.bits,
+            if segment.bits == 1 { "" } else { "s" },
+            segment.max_value(),
+            width = width
+        ));
+        if let Some(id) = id {
+            text.push_str(&format!(": {}", segment.value(id)));
+        }
+        text.push('\n');
+    }
+    text.push('\n');
+    let not_representable = || "after 9999-12-31".to_owned();
+    text.push_str(&format!(
+        "custom_epoch: {}\ntick: {} us\nmax_nodes: {}\nmax_ids_per_tick: {}\nmax_ids_per_second: {}\nexhaustion: {}\n",
+        format_unix_micros(epoch_micros(properties)).unwrap_or_else(not_representable),
+        tick_micros(properties),
+        1_u64 << properties.node_id_bits,
+        max_ids_per_tick(properties),
+        max_ids_per_second(properties),
+        exhaustion(properties).unwrap_or_else(not_representable),
+    ));
+    text
+}
+
+/// One column per bit, with the field names and, for an ID, its bits.
+fn diagram(segments: &[Segment], id: Option<u64>) -> String {
+    let border: String = segments
+        .iter()
+        .map(|segment| format!("+{}", "-".repeat(segment.bits.into())))
+        .chain(std::iter::once("+\n".to_owned()))
+        .collect();
+    let row = |cell: &dyn Fn(&Segment) -> String| -> String {
+        segments
+            .iter()
+            .map(|segment| format!("|{:width$}", cell(segment), width = segment.bits as usize))
+            .chain(std::iter::once("|\n".to_owned()))
+            .collect()
+    };
+    // Bit number of the left-most bit of every field
+    let bit_numbers: String = segments
+        .iter()
+        .map(|segment| {
+            let high_bit = (segment.shift + segment.bits - 1).to_string();
+            format!(
+                " {:width$}",
+                if high_bit.len() <= segment.bits as usize {
+                    high_bit
+                } else {
+                    String::new()
+                },
+                width = segment.bits as usize
+            )
+        })
+        .collect();
+    let mut text = format!("{}\n", bit_numbers.trim_end());
+    text.push_str(&border);
+    text.push_str(&row(&|segment| {
+        segment.name.chars().take(segment.bits.into()).collect()
+    }));
+    if let Some(id) = id {
+        text.push_str(&border);
+        text.push_str(&row(&|segment| {
+            format!(
+                "{:0width$b}",
+                segment.value(id),
+                width = segment.bits as usize
+            )
+        }));
+    }
+    text.push_str(&border);
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn describe_layout() {
+        use super::*;
+        use std::time::Duration;
+        // 2020-01-01T00:00:00Z
+        let custom_epoch = UNIX_EPOCH + Duration::from_secs(1_577_836_800);
+        let mut properties = SequenceProperties::new(custom_epoch, 9, 5, 11, 2, 1, 1500);
+        properties.set_node_subfields(&[("datacenter", 4), ("worker", 5)]);
+        let id = (1 << 20) | (3 << 9) | 5;
+        let value = to_json(&properties, Some(id));
+        assert_eq!(value["custom_epoch"], "2020-01-01T00:00:00Z");
+        assert_eq!(value["max_nodes"], 512);
+        assert_eq!(value["max_ids_per_tick"], 2048);
+        assert_eq!(value["max_ids_per_second"], 20_480_000);
+        assert_eq!(value["fields"][0]["name"], "unused");
+        assert_eq!(value["fields"][1]["high_bit"], 62);
+        assert_eq!(value["fields"][1]["low_bit"], 20);
+        assert_eq!(value["fields"][1]["value"], 1);
+        assert_eq!(value["fields"][2]["value"], 3);
+        assert_eq!(value["node_subfields"][1]["name"], "node_id.worker");
+        assert_eq!(value["node_subfields"][1]["value"], 5);
+        // 2^43 tenths of milliseconds, about 27.9 years
+        assert!(value["exhaustion"]
+            .as_str()
+            .unwrap()
+            .starts_with("2047-11-15T"));
+        let text = to_text(&properties, Some(id));
+        let lines: Vec<&str> = text.lines().collect();
+        // Bit numbers above the first column of each field
+        assert_eq!(lines[0].find("62"), Some(3));
+        assert_eq!(
+            lines[0].split_whitespace().collect::<Vec<&str>>(),
+            vec!["62", "19", "8"]
+        );
+        assert_eq!(lines[1].len(), 64 + 5);
+        assert!(lines[2].starts_with("|u|timestamp"));
+        assert!(lines[4].ends_with("|00000000011|000000101|"));
+        assert!(text.contains("node_id.worker      bits   4..0  ( 5 bits, max 31): 5\n"));
+        // Ticks of 10^18 microseconds, about 31.7 thousand years
+        let properties = SequenceProperties::new(custom_epoch, 1, 0, 1, 18, 0, 1500);
+        let value = to_json(&properties, None);
+        assert_eq!(value["max_ids_per_second"], 2e-12);
+        assert_eq!(value["exhaustion"], Value::Null);
+        let text = to_text(&properties, None);
+        assert!(text.contains("max_ids_per_second: 2e-12\n"));
+        assert!(text.contains("exhaustion: after 9999-12-31\n"));
+    }
+}
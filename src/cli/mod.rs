@@ -0,0 +1,16 @@
+//! Modules of the `sequence_generator` binary.
+pub mod bounds;
+pub mod config_file;
+pub mod coordinator;
+pub mod decode;
+pub mod error;
+pub mod generator;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod http;
+pub mod layout;
+pub mod output;
+pub mod resp;
+pub mod serve;
+#[cfg(unix)]
+pub mod unix_socket;
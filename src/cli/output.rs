@@ -0,0 +1,181 @@
+//! Formats of the IDs printed by the binary, selected with `--output`.
+use super::decode::{other_fields, to_json};
+use super::generator::decode_id;
+use ::sequence_generator::layout::FieldKind;
+use ::sequence_generator::sequence_generator::SequenceProperties;
+use serde_json::Value;
+use std::io::{self, Write};
+use std::str::FromStr;
+use time::UtcOffset;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One ID per line.
+    Plain,
+    /// `index: id` lines.
+    Indexed,
+    /// JSON array of decoded IDs.
+    Json,
+    /// One decoded ID per line as a JSON object.
+    Ndjson,
+    /// `index,id,timestamp,node_id,sequence` rows after a header, followed
+    /// by the node subfields and the other fields of the layout.
+    Csv,
+    /// Packed 8-byte big-endian IDs.
+    Binary,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "plain" => Ok(OutputFormat::Plain),
+            "indexed" => Ok(OutputFormat::Indexed),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "csv" => Ok(OutputFormat::Csv),
+            "binary" => Ok(OutputFormat::Binary),
+            _ => Err(format!(
+                "ERROR: Unknown output format '{}'. Expected 'plain', 'indexed', 'json', 'ndjson', 'csv' or 'binary'",
+                value
+            )),
+        }
+    }
+}
+
+pub fn write_ids(
+    writer: &mut impl Write,
+    format: OutputFormat,
+    ids: &[u64],
+    properties: &SequenceProperties,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Plain => {
+            for id in ids {
+                writeln!(writer, "{}", id)?;
+            }
+        }
+        OutputFormat::Indexed => {
+            for (index, id) in ids.iter().enumerate() {
+                writeln!(writer, "{}: {}", index, id)?;
+            }
+        }
+        OutputFormat::Json => {
+            let decoded = ids
+                .iter()
+                .map(|id| decode(*id, properties))
+                .collect::<io::Result<Vec<Value>>>()?;
+            writeln!(writer, "{}", Value::Array(decoded))?;
+        }
+        OutputFormat::Ndjson => {
+            for id in ids {
+                writeln!(writer, "{}", decode(*id, properties)?)?;
+            }
+        }
+        OutputFormat::Csv => {
+            let mut header = vec!["index,id,timestamp,node_id,sequence".to_owned()];
+            if let Some(node_id_layout) = properties.node_id_layout() {
+                header.extend(
+                    node_id_layout
+                        .subfields()
+                        .iter()
+                        .map(|subfield| format!("node_id.{}", subfield.name)),
+                );
+            }
+            header.extend(
+                properties
+                    .layout()
+                    .fields()
+                    .iter()
+                    .filter(|field| {
+                        !matches!(
+                            field.kind,
+                            FieldKind::Timestamp | FieldKind::NodeId | FieldKind::Sequence
+                        )
+                    })
+                    .map(|field| field.name.clone()),
+            );
+            writeln!(writer, "{}", header.join(","))?;
+            for (index, id) in ids.iter().enumerate() {
+                let decoded = decode_id(*id, properties, UtcOffset::UTC)
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+                let mut row = format!(
+                    "{},{},{},{},{}",
+                    index, id, decoded.timestamp, decoded.node_id, decoded.sequence
+                );
+                for (_, value) in decoded.node_subfields.iter() {
+                    row.push_str(&format!(",{}", value));
+                }
+                for (_, value) in other_fields(&decoded) {
+                    row.push_str(&format!(",{}", value));
+                }
+                writeln!(writer, "{}", row)?;
+            }
+        }
+        OutputFormat::Binary => {
+            for id in ids {
+                writer.write_all(&id.to_be_bytes())?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+fn decode(id: u64, properties: &SequenceProperties) -> io::Result<Value> {
+    decode_id(id, properties, UtcOffset::UTC)
+        .map(|decoded| to_json(&decoded))
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn write_formats() {
+        use super::*;
+        use ::sequence_generator::sequence_generator::generate_id;
+        use std::time::{Duration, UNIX_EPOCH};
+        let custom_epoch = UNIX_EPOCH + Duration::from_secs(1_577_836_800);
+        let properties = SequenceProperties::new(custom_epoch, 9, 3, 11, 2, 0, 1500);
+        let ids: Vec<u64> = (0..2).map(|_| generate_id(&properties).unwrap()).collect();
+        let written = |format| {
+            let mut output = Vec::new();
+            write_ids(&mut output, format, &ids, &properties).unwrap();
+            output
+        };
+        let plain = String::from_utf8(written(OutputFormat::Plain)).unwrap();
+        assert_eq!(plain, format!("{}\n{}\n", ids[0], ids[1]));
+        let indexed = String::from_utf8(written(OutputFormat::Indexed)).unwrap();
+        assert_eq!(indexed, format!("0: {}\n1: {}\n", ids[0], ids[1]));
+        let json: Value = serde_json::from_slice(&written(OutputFormat::Json)).unwrap();
+        assert_eq!(json[1]["id"], ids[1]);
+        assert_eq!(json[1]["node_id"], 3);
+        let ndjson = String::from_utf8(written(OutputFormat::Ndjson)).unwrap();
+        let line: Value = serde_json::from_str(ndjson.lines().nth(1).unwrap()).unwrap();
+        assert_eq!(line, json[1]);
+        let csv = String::from_utf8(written(OutputFormat::Csv)).unwrap();
+        assert!(csv.starts_with("index,id,timestamp,node_id,sequence\n"));
+        assert!(csv
+            .lines()
+            .nth(2)
+            .unwrap()
+            .starts_with(&format!("1,{},", ids[1])));
+        let mut composite = SequenceProperties::new(custom_epoch, 9, 0, 11, 2, 0, 1500);
+        composite.set_node_subfields(&[("datacenter", 4), ("worker", 5)]);
+        composite.set_node_subfield("worker", 7);
+        composite.add_field("type", 4);
+        composite.set_field_value("type", 9);
+        let id = generate_id(&composite).unwrap();
+        let mut output = Vec::new();
+        write_ids(&mut output, OutputFormat::Csv, &[id], &composite).unwrap();
+        let csv = String::from_utf8(output).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "index,id,timestamp,node_id,sequence,node_id.datacenter,node_id.worker,type"
+        );
+        assert!(lines.next().unwrap().ends_with(",7,0,0,7,9"));
+        let binary = written(OutputFormat::Binary);
+        assert_eq!(binary.len(), 16);
+        assert_eq!(&binary[8..], &ids[1].to_be_bytes());
+    }
+}
@@ -0,0 +1,324 @@
+//! Redis protocol (RESP) server mode of the `serve` subcommand, so that
+//! existing Redis clients can fetch IDs:
+//!
+//! - `NEXTID`: one ID as a bulk string, `NEXTID <count>` an array of them
+//! - `GET <key>`: one ID as a bulk string, whatever the key
+//! - `INCR <key>`: one ID as an integer, whatever the key
+//! - `DECODE <id>`: flat array of names and values, as `HGETALL` answers
+//! - `PING`, `QUIT`, and `COMMAND`, `CLIENT`, `SELECT` accepted for clients
+//!   sending them on connection
+//!
+//! Commands are read as RESP arrays of bulk strings, or as inline commands
+//! separated by spaces.
+use super::generator::SharedGenerator;
+use super::serve::ConnectionLimit;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Largest number of arguments and bytes per argument of a command.
+const MAX_ARGUMENTS: usize = 16;
+const MAX_ARGUMENT_BYTES: usize = 512;
+
+#[derive(Debug)]
+pub struct Server {
+    generator: Arc<SharedGenerator>,
+    max_count: usize,
+    connections: Arc<ConnectionLimit>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Reply {
+    Status(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(String),
+    Array(Vec<Reply>),
+}
+
+impl Server {
+    pub fn new(
+        generator: Arc<SharedGenerator>,
+        max_count: usize,
+        connections: Arc<ConnectionLimit>,
+    ) -> Self {
+        Server {
+            generator,
+            max_count,
+            connections,
+        }
+    }
+    /// Answer the clients of `listener`, one thread per connection.
+    pub fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    super::serve::accept_failed("RESP", error);
+                    continue;
+                }
+            };
+            let connection = match self.connections.acquire() {
+                Some(connection) => connection,
+                // Closed by dropping the stream
+                None => {
+                    super::serve::connection_refused("RESP");
+                    continue;
+                }
+            };
+            let server = Arc::clone(&self);
+            thread::spawn(move || {
+                // A client disconnecting abruptly only ends its connection
+                let _ = server.handle(stream);
+                drop(connection);
+            });
+        }
+        Ok(())
+    }
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        // Idle connections are closed like Redis with a `timeout`, pooled
+        // clients reconnect
+        stream.set_read_timeout(Some(Duration::from_secs(60)))?;
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        loop {
+            let command = match read_command(&mut reader)? {
+                Some(Ok(command)) if command.is_empty() => continue,
+                Some(Ok(command)) => command,
+                Some(Err(message)) => {
+                    // The rest of the stream cannot be parsed reliably
+                    write_reply(&mut writer, &Reply::Error(message))?;
+                    return writer.flush();
+                }
+                None => return Ok(()),
+            };
+            let quit = command[0].eq_ignore_ascii_case("QUIT");
+            let reply = if quit {
+                Reply::Status("OK")
+            } else {
+                self.respond(&command)
+            };
+            write_reply(&mut writer, &reply)?;
+            writer.flush()?;
+            if quit {
+                return Ok(());
+            }
+        }
+    }
+    fn respond(&self, command: &[String]) -> Reply {
+        let name = command[0].to_ascii_uppercase();
+        let arguments = &command[1..];
+        let result = match (name.as_str(), arguments.len()) {
+            ("PING", 0) => Ok(Reply::Status("PONG")),
+            ("PING", 1) => Ok(Reply::Bulk(arguments[0].clone())),
+            ("NEXTID", 0) | ("GET", 1) => {
+                self.generate(1).map(|ids| Reply::Bulk(ids[0].to_string()))
+            }
+            ("NEXTID", 1) => arguments[0]
+                .parse::<usize>()
+                .map_err(|_| format!("invalid count '{}'", arguments[0]))
+                .and_then(|count| self.generate(count))
+                .map(|ids| {
+                    Reply::Array(
+                        ids.into_iter()
+                            .map(|id| Reply::Bulk(id.to_string()))
+                            .collect(),
+                    )
+                }),
+            ("INCR", 1) => self.generate(1).and_then(|ids| {
+                i64::try_from(ids[0])
+                    .map(Reply::Integer)
+                    .map_err(|_| format!("ID '{}' does not fit in a RESP integer, use GET", ids[0]))
+            }),
+            ("DECODE", 1) => self.decode(&arguments[0]),
+            ("COMMAND", _) => Ok(Reply::Array(Vec::new())),
+            ("CLIENT", _) | ("SELECT", 1) => Ok(Reply::Status("OK")),
+            ("PING", _)
+            | ("NEXTID", _)
+            | ("GET", _)
+            | ("INCR", _)
+            | ("DECODE", _)
+            | ("SELECT", _) => Err(format!(
+                "wrong number of arguments for '{}' command",
+                command[0].to_ascii_lowercase()
+            )),
+            _ => Err(format!("unknown command '{}'", command[0])),
+        };
+        result.unwrap_or_else(|message| Reply::Error(format!("ERR {}", message)))
+    }
+    fn generate(&self, count: usize) -> Result<Vec<u64>, String> {
+        if count == 0 || count > self.max_count {
+            return Err(format!(
+                "count '{}' must be between 1 and {}",
+                count, self.max_count
+            ));
+        }
+        self.generator
+            .generate(count)
+            .map_err(|error| error.to_string())
+    }
+    fn decode(&self, id: &str) -> Result<Reply, String> {
+        let id = id
+            .parse::<u64>()
+            .map_err(|_| format!("invalid ID '{}'", id))?;
+        let decoded = self.generator.decode(id)?;
+        let mut entries = vec![
+            ("id".to_owned(), decoded.id.to_string()),
+            ("timestamp".to_owned(), decoded.timestamp),
+            ("unix_micros".to_owned(), decoded.unix_micros.to_string()),
+            ("node_id".to_owned(), decoded.node_id.to_string()),
+            ("sequence".to_owned(), decoded.sequence.to_string()),
+        ];
+        entries.extend(
+            decoded
+                .fields
+                .into_iter()
+                .map(|(name, value)| (format!("fields.{}", name), value.to_string())),
+        );
+        entries.extend(
+            decoded
+                .node_subfields
+                .into_iter()
+                .map(|(name, value)| (format!("node_subfields.{}", name), value.to_string())),
+        );
+        Ok(Reply::Array(
+            entries
+                .into_iter()
+                .flat_map(|(name, value)| [Reply::Bulk(name), Reply::Bulk(value)])
+                .collect(),
+        ))
+    }
+}
+
+/// Next command, `None` once the client closed the connection, or the
+/// message of a protocol error.
+fn read_command(reader: &mut impl BufRead) -> io::Result<Option<Result<Vec<String>, String>>> {
+    let line = match read_line(reader)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let count = match line.strip_prefix('*') {
+        Some(count) => count,
+        // Inline command
+        None => {
+            return Ok(Some(Ok(line
+                .split_whitespace()
+                .map(str::to_owned)
+                .collect())))
+        }
+    };
+    let count = match count.parse::<usize>() {
+        Ok(count) if count <= MAX_ARGUMENTS => count,
+        _ => return Ok(Some(Err(format!("ERR invalid array length '{}'", count)))),
+    };
+    let mut command = Vec::with_capacity(count);
+    for _ in 0..count {
+        let header = match read_line(reader)? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        let length = match header.strip_prefix('$').map(str::parse::<usize>) {
+            Some(Ok(length)) if length <= MAX_ARGUMENT_BYTES => length,
+            _ => return Ok(Some(Err(format!("ERR invalid bulk string '{}'", header)))),
+        };
+        // Followed by CRLF
+        let mut argument = vec![0; length + 2];
+        reader.read_exact(&mut argument)?;
+        argument.truncate(length);
+        match String::from_utf8(argument) {
+            Ok(argument) => command.push(argument),
+            Err(_) => return Ok(Some(Err("ERR arguments must be UTF-8".to_owned()))),
+        }
+    }
+    Ok(Some(Ok(command)))
+}
+
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    let mut limited = reader.take(MAX_ARGUMENTS as u64 * MAX_ARGUMENT_BYTES as u64);
+    if limited.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_owned()))
+}
+
+fn write_reply(writer: &mut impl Write, reply: &Reply) -> io::Result<()> {
+    match reply {
+        Reply::Status(status) => write!(writer, "+{}\r\n", status),
+        Reply::Error(message) => write!(writer, "-{}\r\n", message.replace(['\r', '\n'], " ")),
+        Reply::Integer(value) => write!(writer, ":{}\r\n", value),
+        Reply::Bulk(value) => write!(writer, "${}\r\n{}\r\n", value.len(), value),
+        Reply::Array(values) => {
+            write!(writer, "*{}\r\n", values.len())?;
+            values
+                .iter()
+                .try_for_each(|value| write_reply(writer, value))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn serve_over_resp() {
+        use super::*;
+        use ::sequence_generator::sequence_generator::SequenceProperties;
+        use std::time::{Duration, UNIX_EPOCH};
+        let properties = SequenceProperties::new(
+            UNIX_EPOCH + Duration::from_secs(1_577_836_800),
+            9,
+            5,
+            11,
+            3,
+            0,
+            1000,
+        );
+        let generator = Arc::new(SharedGenerator::new(properties));
+        let server = Arc::new(Server::new(
+            generator,
+            100,
+            Arc::new(ConnectionLimit::new(8)),
+        ));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || server.serve(listener));
+        let stream = TcpStream::connect(address).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut line = || {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            line.trim_end().to_owned()
+        };
+        writer
+            .write_all(b"*2\r\n$6\r\nNEXTID\r\n$1\r\n3\r\n")
+            .unwrap();
+        assert_eq!(line(), "*3");
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            assert!(line().starts_with('$'));
+            ids.push(line().parse::<u64>().unwrap());
+        }
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        // Inline command
+        writer.write_all(b"INCR ids\r\n").unwrap();
+        let id = line().strip_prefix(':').unwrap().parse::<u64>().unwrap();
+        assert!(id > ids[2]);
+        writer
+            .write_all(format!("DECODE {}\r\n", ids[0]).as_bytes())
+            .unwrap();
+        let decoded: Vec<String> = (0..line()[1..].parse::<usize>().unwrap())
+            .map(|_| {
+                line();
+                line()
+            })
+            .collect();
+        assert_eq!(decoded[6..8], ["node_id".to_owned(), "5".to_owned()]);
+        writer.write_all(b"NEXTID 101\r\nFLUSHALL\r\n").unwrap();
+        assert!(line().starts_with("-ERR count"));
+        assert!(line().starts_with("-ERR unknown command"));
+    }
+}
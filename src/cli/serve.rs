@@ -0,0 +1,244 @@
+//! `serve` subcommand, answering requests for IDs from a single long-lived
+//! generator shared by every server mode and connection.
+use super::error::CliError;
+use super::generator::SharedGenerator;
+use ::sequence_generator::metrics::GeneratorMetrics;
+use ::sequence_generator::sequence_generator::SequenceProperties;
+use std::io;
+use std::net::TcpListener;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Pause after failing to accept a connection, e.g. out of file descriptors,
+/// before accepting again.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(clap::Args, Debug)]
+pub struct ServeOpt {
+    #[clap(
+        long = "--listen",
+        help = "Address and port to listen on for HTTP requests. [Default: '127.0.0.1:8080' when no other server mode is given]"
+    )]
+    listen: Option<String>,
+    #[cfg(feature = "grpc")]
+    #[clap(
+        long = "--grpc-listen",
+        help = "Address and port to listen on for gRPC requests, see proto/sequence_generator.proto."
+    )]
+    grpc_listen: Option<String>,
+    #[clap(
+        long = "--resp-listen",
+        help = "Address and port to listen on for Redis protocol (RESP) commands such as 'NEXTID 10', 'GET id' or 'DECODE <id>'."
+    )]
+    resp_listen: Option<String>,
+    #[cfg(unix)]
+    #[clap(
+        long = "--unix-socket",
+        help = "Path of a Unix domain socket to listen on for the binary protocol of the library client 'unix_socket::UnixSocketClient'."
+    )]
+    unix_socket: Option<String>,
+    #[clap(
+        long = "--max-count",
+        default_value = "10000",
+        help = "Largest number of IDs returned by a single request."
+    )]
+    max_count: usize,
+    #[clap(
+        long = "--max-connections",
+        default_value = "1024",
+        help = "Largest number of connections served at once over HTTP, the Redis protocol and the Unix domain socket together, each served by its own thread. Further connections are closed right away."
+    )]
+    max_connections: usize,
+    #[clap(
+        long = "--format",
+        default_value = "json",
+        help = "HTTP response format when the request does not ask for one: 'json' or 'text'."
+    )]
+    format: ResponseFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFormat {
+    Json,
+    Text,
+}
+
+impl FromStr for ResponseFormat {
+    type Err = String;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "json" => Ok(ResponseFormat::Json),
+            "text" | "plain" => Ok(ResponseFormat::Text),
+            _ => Err(format!(
+                "ERROR: Unknown response format '{}'. Expected 'json' or 'text'",
+                value
+            )),
+        }
+    }
+}
+
+pub fn run(mut properties: SequenceProperties, mut options: ServeOpt) -> Result<(), CliError> {
+    properties.set_metrics(Arc::new(GeneratorMetrics::new()));
+    let node_id = properties.node_id;
+    let generator = Arc::new(SharedGenerator::new(properties));
+    let connections = Arc::new(ConnectionLimit::new(options.max_connections));
+    // Every server thread sends the error which stopped it
+    let (stopped, first_stopped) = mpsc::channel();
+    let mut servers = 0;
+    #[cfg(feature = "grpc")]
+    if let Some(address) = options.grpc_listen.take() {
+        let listener = bind(&address)?;
+        eprintln!(
+            "Serving IDs of node ID {} over gRPC on '{}'",
+            node_id, address
+        );
+        let server = super::grpc::Server::new(Arc::clone(&generator), options.max_count);
+        let stopped = stopped.clone();
+        thread::spawn(move || {
+            let result = server
+                .serve(listener)
+                .map_err(|error| CliError::io(format!("gRPC server stopped: {}", error)));
+            stopped.send(result).ok();
+        });
+        servers += 1;
+    }
+    if let Some(address) = options.resp_listen.take() {
+        let listener = bind(&address)?;
+        eprintln!(
+            "Serving IDs of node ID {} over the Redis protocol on '{}'",
+            node_id, address
+        );
+        let server = super::resp::Server::new(
+            Arc::clone(&generator),
+            options.max_count,
+            Arc::clone(&connections),
+        );
+        let stopped = stopped.clone();
+        thread::spawn(move || {
+            let result = Arc::new(server)
+                .serve(listener)
+                .map_err(|error| CliError::io(format!("RESP server stopped: {}", error)));
+            stopped.send(result).ok();
+        });
+        servers += 1;
+    }
+    #[cfg(unix)]
+    if let Some(path) = options.unix_socket.take() {
+        let listener = super::unix_socket::bind(Path::new(&path))
+            .map_err(|error| CliError::io(format!("Could not listen on '{}': {}", path, error)))?;
+        eprintln!(
+            "Serving IDs of node ID {} on Unix domain socket '{}'",
+            node_id, path
+        );
+        let server = super::unix_socket::Server::new(
+            Arc::clone(&generator),
+            options.max_count,
+            Arc::clone(&connections),
+        );
+        let stopped = stopped.clone();
+        thread::spawn(move || {
+            let result = Arc::new(server).serve(listener).map_err(|error| {
+                CliError::io(format!("Unix domain socket server stopped: {}", error))
+            });
+            stopped.send(result).ok();
+        });
+        servers += 1;
+    }
+    if options.listen.is_none() && servers == 0 {
+        options.listen = Some("127.0.0.1:8080".to_owned());
+    }
+    if let Some(address) = options.listen.take() {
+        let listener = bind(&address)?;
+        eprintln!(
+            "Serving IDs of node ID {} over HTTP on '{}'",
+            node_id, address
+        );
+        let server = super::http::Server::new(
+            Arc::clone(&generator),
+            options.max_count,
+            options.format,
+            connections,
+        );
+        let stopped = stopped.clone();
+        thread::spawn(move || {
+            let result = Arc::new(server)
+                .serve(listener)
+                .map_err(|error| CliError::io(format!("HTTP server stopped: {}", error)));
+            stopped.send(result).ok();
+        });
+    }
+    drop(stopped);
+    // Serve until the first server stops with an error, or every server thread
+    // has ended
+    first_stopped.recv().unwrap_or(Ok(()))
+}
+
+fn bind(address: &str) -> Result<TcpListener, CliError> {
+    TcpListener::bind(address)
+        .map_err(|error| CliError::io(format!("Could not listen on '{}': {}", address, error)))
+}
+
+/// Report a connection `server` could not accept and keep serving the
+/// others. Pauses unless only that connection failed.
+pub fn accept_failed(server: &str, error: io::Error) {
+    eprintln!(
+        "WARNING: {} server could not accept a connection: {}",
+        server, error
+    );
+    match error.kind() {
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::Interrupted => {}
+        _ => thread::sleep(ACCEPT_RETRY_DELAY),
+    }
+}
+
+/// Report a connection closed because `server` already serves the most
+/// connections allowed.
+pub fn connection_refused(server: &str) {
+    eprintln!(
+        "WARNING: {} server closed a connection, the maximum number of connections is reached",
+        server
+    );
+}
+
+/// Number of connections served at once by the thread per connection
+/// servers, shared between them.
+#[derive(Debug)]
+pub struct ConnectionLimit {
+    active: AtomicUsize,
+    max: usize,
+}
+
+/// Place of a connection being served, given back when dropped.
+#[derive(Debug)]
+pub struct Connection(Arc<ConnectionLimit>);
+
+impl ConnectionLimit {
+    pub fn new(max: usize) -> Self {
+        ConnectionLimit {
+            active: AtomicUsize::new(0),
+            max,
+        }
+    }
+    /// Place for one more connection, if fewer than the maximum are served.
+    pub fn acquire(self: &Arc<Self>) -> Option<Connection> {
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |active| {
+                (active < self.max).then_some(active + 1)
+            })
+            .ok()
+            .map(|_| Connection(Arc::clone(self)))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,151 @@
+//! Unix domain socket server mode of the `serve` subcommand, speaking the
+//! binary protocol of `sequence_generator::unix_socket`.
+use super::generator::SharedGenerator;
+use super::serve::ConnectionLimit;
+use ::sequence_generator::unix_socket::{read_request, write_error, write_ids};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fs, thread};
+
+#[derive(Debug)]
+pub struct Server {
+    generator: Arc<SharedGenerator>,
+    max_count: usize,
+    connections: Arc<ConnectionLimit>,
+}
+
+impl Server {
+    pub fn new(
+        generator: Arc<SharedGenerator>,
+        max_count: usize,
+        connections: Arc<ConnectionLimit>,
+    ) -> Self {
+        Server {
+            generator,
+            max_count,
+            connections,
+        }
+    }
+    /// Answer the clients of `listener`, one thread per connection.
+    pub fn serve(self: Arc<Self>, listener: UnixListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    super::serve::accept_failed("Unix domain socket", error);
+                    continue;
+                }
+            };
+            let connection = match self.connections.acquire() {
+                Some(connection) => connection,
+                // Closed by dropping the stream
+                None => {
+                    super::serve::connection_refused("Unix domain socket");
+                    continue;
+                }
+            };
+            let server = Arc::clone(&self);
+            thread::spawn(move || {
+                // A client disconnecting abruptly only ends its connection
+                let _ = server.handle(stream);
+                drop(connection);
+            });
+        }
+        Ok(())
+    }
+    fn handle(&self, stream: UnixStream) -> io::Result<()> {
+        // Idle connections are closed as with the HTTP and RESP servers
+        stream.set_read_timeout(Some(Duration::from_secs(60)))?;
+        let mut writer = BufWriter::new(stream.try_clone()?);
+        let mut reader = BufReader::new(stream);
+        while let Some(count) = read_request(&mut reader)? {
+            let count = count as usize;
+            if count == 0 || count > self.max_count {
+                write_error(
+                    &mut writer,
+                    &format!("count '{}' must be between 1 and {}", count, self.max_count),
+                )?;
+            } else {
+                match self.generator.generate(count) {
+                    Ok(ids) => write_ids(&mut writer, &ids)?,
+                    Err(error) => write_error(&mut writer, &error.to_string())?,
+                }
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Listen on `path`, replacing the socket file left behind by a previous
+/// run but not one another server is listening on, nor any other file.
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "a file other than a socket exists at this path",
+            ));
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another server is listening on this socket",
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn serve_over_unix_socket() {
+        use super::*;
+        use ::sequence_generator::sequence_generator::SequenceProperties;
+        use ::sequence_generator::unix_socket::UnixSocketClient;
+        use std::time::UNIX_EPOCH;
+        let properties = SequenceProperties::new(
+            UNIX_EPOCH + Duration::from_secs(1_577_836_800),
+            9,
+            5,
+            11,
+            3,
+            0,
+            1000,
+        );
+        let generator = Arc::new(SharedGenerator::new(properties));
+        let server = Arc::new(Server::new(
+            generator,
+            100,
+            Arc::new(ConnectionLimit::new(8)),
+        ));
+        let path = std::env::temp_dir().join(format!(
+            "sequence-generator-serve-{}.sock",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let listener = bind(&path).unwrap();
+        thread::spawn(move || server.serve(listener));
+        let mut client = UnixSocketClient::connect(&path).unwrap();
+        let ids = client.next_ids(3).unwrap();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        let error = client.next_ids(101).unwrap_err();
+        assert_eq!(error.to_string(), "count '101' must be between 1 and 100");
+        // Still usable after an error
+        assert!(client.next_id().unwrap() > ids[2]);
+        let error = bind(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+        fs::remove_file(&path).unwrap();
+        // Only socket files are replaced
+        fs::write(&path, "not a socket").unwrap();
+        let error = bind(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,380 @@
+//! Clients of the server modes of `sequence_generator serve`, and a
+//! `BufferedClient` prefetching IDs from any of them to save a round trip
+//! per ID.
+use crate::sequence_generator::SequenceGeneratorError;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Pause after a failed fetch before the background refill tries again.
+const REFILL_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// Default time to connect, send a request or read a response of an
+/// `HttpClient`.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Server handing out batches of consecutive IDs.
+pub trait IdSource: Send + 'static {
+    fn fetch(&mut self, count: u32) -> io::Result<Vec<u64>>;
+}
+
+#[cfg(unix)]
+impl IdSource for crate::unix_socket::UnixSocketClient {
+    fn fetch(&mut self, count: u32) -> io::Result<Vec<u64>> {
+        self.next_ids(count)
+    }
+}
+
+/// Client of the HTTP server mode, fetching `/ids?n=` as plain text over a
+/// keep-alive connection, opened again after a failure. Connecting, sending
+/// a request and reading its response each time out after 5 seconds unless
+/// set otherwise with `set_timeout`.
+#[derive(Debug)]
+pub struct HttpClient {
+    address: String,
+    timeout: Duration,
+    connection: Option<BufReader<TcpStream>>,
+}
+
+impl HttpClient {
+    /// Client of the server listening at `address`, e.g. `127.0.0.1:8080`.
+    pub fn new(address: impl Into<String>) -> Self {
+        HttpClient {
+            address: address.into(),
+            timeout: HTTP_TIMEOUT,
+            connection: None,
+        }
+    }
+    /// Timeout of connecting, sending a request and reading its response,
+    /// used from the next connection on.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+    pub fn next_ids(&mut self, count: u32) -> io::Result<Vec<u64>> {
+        let result = self.request(count);
+        if result.is_err() {
+            self.connection = None;
+        }
+        result
+    }
+    fn request(&mut self, count: u32) -> io::Result<Vec<u64>> {
+        if self.connection.is_none() {
+            let address = self
+                .address
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| invalid_data(format!("no address for '{}'", self.address)))?;
+            let stream = TcpStream::connect_timeout(&address, self.timeout)?;
+            stream.set_nodelay(true)?;
+            stream.set_read_timeout(Some(self.timeout))?;
+            stream.set_write_timeout(Some(self.timeout))?;
+            self.connection = Some(BufReader::new(stream));
+        }
+        let connection = self.connection.as_mut().unwrap();
+        write!(
+            connection.get_mut(),
+            "GET /ids?n={}&format=text HTTP/1.1\r\nHost: {}\r\n\r\n",
+            count,
+            self.address
+        )?;
+        let mut status = String::new();
+        connection.read_line(&mut status)?;
+        let mut content_length = None;
+        loop {
+            let mut header = String::new();
+            if connection.read_line(&mut header)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "server closed the connection",
+                ));
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse::<u64>().ok();
+                }
+            }
+        }
+        let content_length =
+            content_length.ok_or_else(|| invalid_data("response without Content-Length"))?;
+        let mut body = String::new();
+        connection
+            .by_ref()
+            .take(content_length)
+            .read_to_string(&mut body)?;
+        if status.split_whitespace().nth(1) != Some("200") {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("{}: {}", status.trim(), body.trim()),
+            ));
+        }
+        body.lines()
+            .map(|line| {
+                line.trim()
+                    .parse::<u64>()
+                    .map_err(|_| invalid_data(format!("invalid ID '{}'", line)))
+            })
+            .collect()
+    }
+}
+
+impl IdSource for HttpClient {
+    fn fetch(&mut self, count: u32) -> io::Result<Vec<u64>> {
+        self.next_ids(count)
+    }
+}
+
+/// Sizes of the local buffer of a `BufferedClient`, validated on creation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferOptions {
+    capacity: u32,
+    low_water_mark: u32,
+    max_age: Option<Duration>,
+}
+
+impl BufferOptions {
+    pub fn new(capacity: u32, low_water_mark: u32, max_age: Option<Duration>) -> Self {
+        Self::try_new(capacity, low_water_mark, max_age)
+            .unwrap_or_else(|error| panic!("ERROR: {}", error))
+    }
+    /// `BufferOptions::new` returning invalid sizes as
+    /// `SequenceGeneratorError::InvalidConfiguration` instead of panicking.
+    pub fn try_new(
+        capacity: u32,
+        low_water_mark: u32,
+        max_age: Option<Duration>,
+    ) -> Result<Self, SequenceGeneratorError> {
+        if low_water_mark == 0 || low_water_mark >= capacity {
+            return Err(SequenceGeneratorError::InvalidConfiguration(format!(
+                "Low-water mark '{}' must be larger than 0 and smaller than the buffer capacity '{}'.",
+                low_water_mark, capacity
+            )));
+        }
+        Ok(BufferOptions {
+            capacity,
+            low_water_mark,
+            max_age,
+        })
+    }
+    /// IDs held once refilled, fetched in a single batch.
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+    /// The buffer is refilled in the background once it holds fewer IDs.
+    pub fn low_water_mark(&self) -> u32 {
+        self.low_water_mark
+    }
+    /// IDs fetched longer ago are discarded, so that IDs handed out keep
+    /// roughly the order of the time they are used at.
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+}
+
+/// IDs prefetched in batches from an `IdSource` by a background thread.
+/// `next_id` only waits for the server when the buffer is empty.
+#[derive(Debug)]
+pub struct BufferedClient {
+    shared: Arc<Shared>,
+    refill: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    options: BufferOptions,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    ids: VecDeque<(Instant, u64)>,
+    error: Option<io::Error>,
+    /// The refill thread is waiting for the source.
+    fetching: bool,
+    stopped: bool,
+}
+
+impl BufferedClient {
+    pub fn new(source: impl IdSource, options: BufferOptions) -> Self {
+        let shared = Arc::new(Shared {
+            options,
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+        });
+        let refill_shared = Arc::clone(&shared);
+        let refill = thread::spawn(move || refill_shared.refill(source));
+        BufferedClient {
+            shared,
+            refill: Some(refill),
+        }
+    }
+    pub fn options(&self) -> BufferOptions {
+        self.shared.options
+    }
+    /// IDs in the buffer, not counting those about to be discarded.
+    pub fn buffered(&self) -> usize {
+        self.shared.lock().ids.len()
+    }
+    /// Next buffered ID, waiting for a refill if the buffer is empty. Fails
+    /// with the error of the last refill if it failed meanwhile.
+    pub fn next_id(&self) -> io::Result<u64> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some((_, id)) = state.ids.pop_front() {
+                if state.ids.len() < self.shared.options.low_water_mark as usize {
+                    self.shared.changed.notify_all();
+                }
+                return Ok(id);
+            }
+            if let Some(error) = state.error.take() {
+                return Err(error);
+            }
+            self.shared.changed.notify_all();
+            state = self.shared.changed.wait(state).unwrap();
+            self.shared.discard_expired(&mut state);
+        }
+    }
+}
+
+impl Drop for BufferedClient {
+    /// The refill thread is left to finish on its own when waiting for the
+    /// source, instead of blocking on a slow server.
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.stopped = true;
+        let fetching = state.fetching;
+        drop(state);
+        self.shared.changed.notify_all();
+        if let Some(refill) = self.refill.take() {
+            if !fetching {
+                let _ = refill.join();
+            }
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        let mut state = self.state.lock().unwrap();
+        self.discard_expired(&mut state);
+        state
+    }
+    fn discard_expired(&self, state: &mut State) {
+        if let Some(max_age) = self.options.max_age {
+            while state
+                .ids
+                .front()
+                .map_or(false, |(fetched, _)| fetched.elapsed() > max_age)
+            {
+                state.ids.pop_front();
+            }
+        }
+    }
+    /// Top up the buffer whenever it falls below the low-water mark, until
+    /// the client is dropped.
+    fn refill(&self, mut source: impl IdSource) {
+        let mut state = self.lock();
+        loop {
+            if state.stopped {
+                return;
+            }
+            if state.ids.len() >= self.options.low_water_mark as usize {
+                // Woken up early enough to discard expired IDs
+                state = match self.options.max_age {
+                    Some(max_age) => self.changed.wait_timeout(state, max_age).unwrap().0,
+                    None => self.changed.wait(state).unwrap(),
+                };
+                self.discard_expired(&mut state);
+                continue;
+            }
+            let count = self.options.capacity - state.ids.len() as u32;
+            state.fetching = true;
+            drop(state);
+            let fetched = source.fetch(count);
+            state = self.lock();
+            state.fetching = false;
+            match fetched {
+                Ok(ids) => {
+                    let now = Instant::now();
+                    state.ids.extend(ids.into_iter().map(|id| (now, id)));
+                    state.error = None;
+                    self.changed.notify_all();
+                }
+                Err(error) => {
+                    state.error = Some(error);
+                    self.changed.notify_all();
+                    state = self
+                        .changed
+                        .wait_timeout(state, REFILL_RETRY_DELAY)
+                        .unwrap()
+                        .0;
+                }
+            }
+        }
+    }
+}
+
+fn invalid_data(message: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+    /// Consecutive IDs from 1, with the next ID and the number of fetches
+    /// readable by the test.
+    #[derive(Default)]
+    struct Counter {
+        next: Arc<AtomicU64>,
+        fetches: Arc<AtomicU32>,
+    }
+
+    impl IdSource for Counter {
+        fn fetch(&mut self, count: u32) -> io::Result<Vec<u64>> {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+            let first = self.next.fetch_add(u64::from(count), Ordering::SeqCst) + 1;
+            Ok((first..first + u64::from(count)).collect())
+        }
+    }
+
+    #[test]
+    fn buffered_refill() {
+        let source = Counter::default();
+        let fetches = Arc::clone(&source.fetches);
+        let client = BufferedClient::new(source, BufferOptions::new(10, 4, None));
+        let ids: Vec<u64> = (0..7).map(|_| client.next_id().unwrap()).collect();
+        assert_eq!(ids, vec![1, 2, 3, 4, 5, 6, 7]);
+        // Topped up in the background below the low-water mark
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while client.buffered() < 10 {
+            assert!(Instant::now() < deadline, "buffer was not refilled");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+        assert_eq!(client.next_id().unwrap(), 8);
+        assert!(BufferOptions::try_new(10, 10, None).is_err());
+        assert!(BufferOptions::try_new(10, 0, None).is_err());
+    }
+
+    #[test]
+    fn buffered_expiry() {
+        let source = Counter::default();
+        let next = Arc::clone(&source.next);
+        let max_age = Duration::from_millis(50);
+        let client = BufferedClient::new(source, BufferOptions::new(10, 4, Some(max_age)));
+        client.next_id().unwrap();
+        let fetched = next.load(Ordering::SeqCst);
+        // Every ID fetched so far expires, however long the sleep lasts, and
+        // is discarded for IDs fetched again
+        thread::sleep(max_age * 2);
+        assert!(client.next_id().unwrap() > fetched);
+    }
+}
@@ -0,0 +1,382 @@
+use crate::checksum::ChecksumAlgorithm;
+use crate::layout::FieldKind;
+use crate::sequence_generator::{SequenceGeneratorError, SequenceProperties};
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Layout part of `SequenceProperties`, without any generation state. It can
+/// be stored, compared and (with the `serde` feature) loaded from JSON/YAML,
+/// then turned into a generator with `build`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SequenceConfig {
+    /// RFC-3339 datetime when serialized, e.g. "2020-01-01T00:00:00Z".
+    #[cfg_attr(feature = "serde", serde(with = "rfc3339"))]
+    pub custom_epoch: SystemTime,
+    pub node_id_bits: u8,
+    pub node_id: u16,
+    pub sequence_bits: u8,
+    pub micros_ten_power: u8,
+    pub unused_bits: u8,
+    #[cfg_attr(feature = "serde", serde(alias = "cooldown_ns"))]
+    pub backoff_cooldown_start_ns: u64,
+    /// Fields after the timestamp, from left-most to right-most bits.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub field_order: Option<Vec<String>>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    pub node_subfields: Vec<FieldConfig>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    pub extra_fields: Vec<FieldConfig>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub checksum: Option<ChecksumConfig>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub layout_version: Option<LayoutVersionConfig>,
+}
+
+/// Named node ID subfield or extra field, with its width and value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FieldConfig {
+    pub name: String,
+    pub bits: u8,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub value: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ChecksumConfig {
+    pub algorithm: ChecksumAlgorithm,
+    pub bits: u8,
+}
+
+/// Version stored in the left-most `bits` of every ID, see
+/// `SequenceProperties::set_layout_version`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LayoutVersionConfig {
+    pub version: u64,
+    pub bits: u8,
+}
+
+impl Default for SequenceConfig {
+    /// Defaults of the `sequence_generator` binary: 2020-01-01 custom epoch,
+    /// tenths of milliseconds, 11 sequence bits and 9 node ID bits.
+    fn default() -> Self {
+        SequenceConfig {
+            custom_epoch: UNIX_EPOCH + Duration::from_secs(1_577_836_800),
+            node_id_bits: 9,
+            node_id: 0,
+            sequence_bits: 11,
+            micros_ten_power: 2,
+            unused_bits: 0,
+            backoff_cooldown_start_ns: 1000,
+            field_order: None,
+            node_subfields: Vec::new(),
+            extra_fields: Vec::new(),
+            checksum: None,
+            layout_version: None,
+        }
+    }
+}
+
+impl SequenceConfig {
+    /// Names accepted by `SequenceConfig::preset`.
+    pub const PRESETS: &'static [&'static str] =
+        &["default", "twitter", "discord", "instagram", "sonyflake"];
+
+    /// Well-known layouts, all with node ID 0:
+    /// - `default`: same as `SequenceConfig::default()`.
+    /// - `twitter`: 1 unused bit, 41 bits of milliseconds since 2010-11-04,
+    ///   10 bits of node ID and a 12-bit sequence in the right-most bits.
+    /// - `discord`: 42 bits of milliseconds since 2015-01-01, 5-bit worker
+    ///   and 5-bit process node ID subfields and a 12-bit sequence.
+    /// - `instagram`: 41 bits of milliseconds since 2011-08-24, 13 bits of
+    ///   logical shard as node ID and a 10-bit sequence.
+    /// - `sonyflake`: 1 unused bit, 39 bits of 10 milliseconds since
+    ///   2014-09-01, an 8-bit sequence and 16 bits of machine ID.
+    pub fn preset(name: &str) -> Option<SequenceConfig> {
+        let mut config = SequenceConfig::default();
+        let twitter_order = Some(vec![
+            FieldKind::NodeId.name().to_owned(),
+            FieldKind::Sequence.name().to_owned(),
+        ]);
+        match name.trim().to_ascii_lowercase().as_str() {
+            "default" => {}
+            "twitter" => {
+                config.custom_epoch = UNIX_EPOCH + Duration::from_millis(1_288_834_974_657);
+                config.unused_bits = 1;
+                config.node_id_bits = 10;
+                config.sequence_bits = 12;
+                config.micros_ten_power = 3;
+                config.field_order = twitter_order;
+            }
+            "discord" => {
+                config.custom_epoch = UNIX_EPOCH + Duration::from_millis(1_420_070_400_000);
+                config.node_id_bits = 10;
+                config.sequence_bits = 12;
+                config.micros_ten_power = 3;
+                config.field_order = twitter_order;
+                config.node_subfields = ["worker", "process"]
+                    .iter()
+                    .map(|name| FieldConfig {
+                        name: name.to_string(),
+                        bits: 5,
+                        value: 0,
+                    })
+                    .collect();
+            }
+            "instagram" => {
+                config.custom_epoch = UNIX_EPOCH + Duration::from_millis(1_314_220_021_721);
+                config.node_id_bits = 13;
+                config.sequence_bits = 10;
+                config.micros_ten_power = 3;
+                config.field_order = twitter_order;
+            }
+            "sonyflake" => {
+                config.custom_epoch = UNIX_EPOCH + Duration::from_secs(1_409_529_600);
+                config.unused_bits = 1;
+                config.node_id_bits = 16;
+                config.sequence_bits = 8;
+                config.micros_ten_power = 4;
+            }
+            _ => return None,
+        }
+        Some(config)
+    }
+    /// Create the generator described by this configuration. Panics on
+    /// invalid values like `SequenceProperties::new`.
+    pub fn build(&self) -> SequenceProperties {
+        self.try_build()
+            .unwrap_or_else(|error| panic!("ERROR: {}", error))
+    }
+    /// Create the generator described by this configuration, returning
+    /// invalid values, e.g. of a deserialized configuration, as
+    /// `SequenceGeneratorError::InvalidConfiguration`.
+    pub fn try_build(&self) -> Result<SequenceProperties, SequenceGeneratorError> {
+        let mut properties = SequenceProperties::try_new(
+            self.custom_epoch,
+            self.node_id_bits,
+            self.node_id,
+            self.sequence_bits,
+            self.micros_ten_power,
+            self.unused_bits,
+            self.backoff_cooldown_start_ns,
+        )?;
+        if !self.node_subfields.is_empty() {
+            let subfields: Vec<(&str, u8)> = self
+                .node_subfields
+                .iter()
+                .map(|subfield| (subfield.name.as_str(), subfield.bits))
+                .collect();
+            properties.try_set_node_subfields(&subfields)?;
+            for subfield in self.node_subfields.iter() {
+                let value = u16::try_from(subfield.value).map_err(|_| {
+                    SequenceGeneratorError::InvalidConfiguration(format!(
+                        "Node ID subfield '{}' value '{}' is larger than 16 bits.",
+                        subfield.name, subfield.value
+                    ))
+                })?;
+                properties.try_set_node_subfield(&subfield.name, value)?;
+            }
+        }
+        for extra_field in self.extra_fields.iter() {
+            properties.try_add_field(&extra_field.name, extra_field.bits)?;
+            properties.try_set_field_value(&extra_field.name, extra_field.value)?;
+        }
+        if let Some(checksum) = self.checksum {
+            properties.try_set_checksum(checksum.algorithm, checksum.bits)?;
+        }
+        if let Some(layout_version) = self.layout_version {
+            properties.try_set_layout_version(layout_version.version, layout_version.bits)?;
+        }
+        if let Some(field_order) = self.field_order.as_ref() {
+            let field_order: Vec<&str> = field_order.iter().map(String::as_str).collect();
+            properties.try_set_field_order(&field_order)?;
+        }
+        Ok(properties)
+    }
+}
+
+impl SequenceProperties {
+    /// Layout part of these properties, which `SequenceConfig::build` turns
+    /// back into an equivalent generator.
+    pub fn config(&self) -> SequenceConfig {
+        let layout = self.layout();
+        let node_subfields = self
+            .node_id_layout()
+            .map(|node_id_layout| {
+                node_id_layout
+                    .split(self.node_id)
+                    .into_iter()
+                    .zip(node_id_layout.subfields())
+                    .map(|((name, value), subfield)| FieldConfig {
+                        name,
+                        bits: subfield.bits,
+                        value: value as u64,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let extra_fields = layout
+            .fields()
+            .iter()
+            .filter(|field| field.kind == FieldKind::Extra)
+            .map(|field| FieldConfig {
+                name: field.name.clone(),
+                bits: field.bits,
+                value: self.field_value(&field.name).unwrap_or(0),
+            })
+            .collect();
+        let checksum = self.checksum().map(|algorithm| ChecksumConfig {
+            algorithm,
+            bits: layout.field(FieldKind::Checksum).unwrap().bits,
+        });
+        let layout_version = self.layout_version().map(|version| LayoutVersionConfig {
+            version,
+            bits: layout.field(FieldKind::Version).unwrap().bits,
+        });
+        SequenceConfig {
+            custom_epoch: self.custom_epoch,
+            node_id_bits: self.node_id_bits,
+            node_id: self.node_id,
+            sequence_bits: self.sequence_bits,
+            micros_ten_power: self.micros_ten_power,
+            unused_bits: self.unused_bits,
+            backoff_cooldown_start_ns: self.backoff_cooldown_start_ns,
+            field_order: Some(
+                layout
+                    .fields()
+                    .iter()
+                    .filter(|field| {
+                        field.kind != FieldKind::Timestamp && field.kind != FieldKind::Version
+                    })
+                    .map(|field| field.name.clone())
+                    .collect(),
+            ),
+            node_subfields,
+            extra_fields,
+            checksum,
+            layout_version,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+mod rfc3339 {
+    use serde::de::Error as _;
+    use serde::ser::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::SystemTime;
+    use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        let formatted = OffsetDateTime::from(*time)
+            .format(&Rfc3339)
+            .map_err(S::Error::custom)?;
+        serializer.serialize_str(&formatted)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        let parsed = OffsetDateTime::parse(&value, &Rfc3339).map_err(|_| {
+            D::Error::custom(format!(
+                "could not parse custom_epoch '{}' as an RFC-3339/ISO-8601 datetime",
+                value
+            ))
+        })?;
+        Ok(SystemTime::from(parsed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn config_round_trip() {
+        use super::*;
+        let mut properties = SequenceConfig::default().build();
+        properties.set_node_subfields(&[("datacenter", 4), ("worker", 5)]);
+        properties.set_node_subfield("worker", 7);
+        properties.add_field("type", 3);
+        properties.set_field_value("type", 5);
+        properties.set_checksum(ChecksumAlgorithm::Crc, 4);
+        properties.set_field_order(&["type", "node_id", "sequence", "checksum"]);
+        properties.set_layout_version(2, 3);
+        let config = properties.config();
+        let rebuilt = config.build();
+        assert_eq!(rebuilt.layout(), properties.layout());
+        assert_eq!(rebuilt.node_id, 7);
+        assert_eq!(rebuilt.field_value("type"), Some(5));
+        assert_eq!(rebuilt.layout_version(), Some(2));
+        assert_eq!(rebuilt.config(), config);
+        let invalid = SequenceConfig {
+            sequence_bits: 17,
+            ..config
+        };
+        assert!(matches!(
+            invalid.try_build(),
+            Err(SequenceGeneratorError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[test]
+    fn presets() {
+        use super::*;
+        for name in SequenceConfig::PRESETS {
+            let properties = SequenceConfig::preset(name).unwrap().build();
+            assert!(properties.timestamp_bits >= 39);
+        }
+        let twitter = SequenceConfig::preset("twitter").unwrap().build();
+        assert_eq!(twitter.timestamp_bits, 41);
+        assert_eq!(
+            twitter.layout().field(FieldKind::Sequence).unwrap().shift,
+            0
+        );
+        let discord = SequenceConfig::preset("Discord").unwrap().build();
+        assert_eq!(discord.timestamp_bits, 42);
+        assert_eq!(discord.node_id_layout().unwrap().subfields().len(), 2);
+        assert!(SequenceConfig::preset("snowflake").is_none());
+        // The checksum stays in the right-most bits, after the preset order
+        for name in ["twitter", "discord", "instagram"] {
+            let mut config = SequenceConfig::preset(name).unwrap();
+            config.checksum = Some(ChecksumConfig {
+                algorithm: ChecksumAlgorithm::Crc,
+                bits: 4,
+            });
+            let properties = config.build();
+            let layout = properties.layout();
+            assert_eq!(layout.field(FieldKind::Checksum).unwrap().shift, 0);
+            assert_eq!(layout.field(FieldKind::Sequence).unwrap().shift, 4);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn config_json() {
+        use super::*;
+        let config: SequenceConfig = serde_json::from_str(
+            r#"{"custom_epoch": "2010-11-04T01:42:54Z", "node_id_bits": 10, "sequence_bits": 12,
+                "micros_ten_power": 3, "unused_bits": 1, "node_id": 128,
+                "field_order": ["node_id", "sequence"]}"#,
+        )
+        .unwrap();
+        assert_eq!(config.backoff_cooldown_start_ns, 1000);
+        assert_eq!(
+            config.custom_epoch,
+            UNIX_EPOCH + Duration::from_secs(1_288_834_974)
+        );
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["custom_epoch"], "2010-11-04T01:42:54Z");
+        assert_eq!(
+            serde_json::from_value::<SequenceConfig>(json).unwrap(),
+            config
+        );
+        assert!(
+            serde_json::from_str::<SequenceConfig>(r#"{"custom_epoch": "yesterday"}"#).is_err()
+        );
+    }
+}
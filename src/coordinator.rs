@@ -0,0 +1,406 @@
+//! Node IDs handed out over TCP by a `Coordinator`, for fleets without
+//! ZooKeeper or etcd. Clients hold a `CoordinatorLease` kept alive by
+//! heartbeats and stop generating IDs when it is lost.
+//!
+//! The protocol is line based text, one request and one response per line:
+//!
+//! - `ACQUIRE <node_id_bits>`: `LEASE <node_id> <token> <lease_ttl_ms>`
+//! - `HEARTBEAT <node_id> <token>`: `OK <lease_ttl_ms>`
+//! - `RELEASE <node_id> <token>`: `OK 0`
+//!
+//! Failed requests are answered with `ERROR <message>`.
+use crate::sequence_generator::SequenceGeneratorError;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Time to connect to the coordinator and have a lease acquired, after
+/// which read and write timeouts follow the heartbeat interval.
+const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause after failing to accept a connection before accepting again.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Hands out node IDs to clients and tracks them with heartbeats. A node ID
+/// is only reissued `reissue_delay` after the last heartbeat of its previous
+/// holder, which stops generating `lease_ttl` after its last heartbeat.
+///
+/// The leases live in memory. Clients lose their lease with the connection
+/// to the coordinator, and after a restart no node ID is handed out for
+/// `reissue_delay`, until the clients of the previous run have stopped.
+#[derive(Debug)]
+pub struct Coordinator {
+    pub node_id_bits: u8,
+    pub lease_ttl: Duration,
+    pub reissue_delay: Duration,
+    started: Instant,
+    leases: Mutex<HashMap<u16, Lease>>,
+    next_token: AtomicU64,
+    /// Randomly keyed hash of `next_token`, so that tokens of other clients
+    /// cannot be guessed
+    token_hasher: RandomState,
+}
+
+#[derive(Debug)]
+struct Lease {
+    token: u64,
+    last_heartbeat: Instant,
+    /// Released by its holder, kept until `reissue_delay` after the last
+    /// heartbeat but no longer renewed
+    released: bool,
+}
+
+impl Coordinator {
+    pub fn new(node_id_bits: u8, lease_ttl: Duration, reissue_delay: Duration) -> Self {
+        Coordinator::try_new(node_id_bits, lease_ttl, reissue_delay)
+            .unwrap_or_else(|error| panic!("ERROR: {}", error))
+    }
+    /// `Coordinator::new` returning invalid node ID bits or a reissue delay
+    /// not longer than the lease time to live as
+    /// `SequenceGeneratorError::InvalidConfiguration` instead of panicking.
+    pub fn try_new(
+        node_id_bits: u8,
+        lease_ttl: Duration,
+        reissue_delay: Duration,
+    ) -> Result<Self, SequenceGeneratorError> {
+        if node_id_bits == 0 || node_id_bits > 16 {
+            return Err(SequenceGeneratorError::InvalidConfiguration(format!(
+                "node_id_bits '{}' must be between 1 and 16.",
+                node_id_bits
+            )));
+        }
+        if reissue_delay <= lease_ttl {
+            return Err(SequenceGeneratorError::InvalidConfiguration(format!(
+                "Reissue delay {:?} must be longer than the lease time to live {:?}.",
+                reissue_delay, lease_ttl
+            )));
+        }
+        Ok(Coordinator {
+            node_id_bits,
+            lease_ttl,
+            reissue_delay,
+            started: Instant::now(),
+            leases: Mutex::new(HashMap::new()),
+            next_token: AtomicU64::new(0),
+            token_hasher: RandomState::new(),
+        })
+    }
+    /// Answer the clients of `listener`, one thread per connection.
+    pub fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_error) => {
+                    // Out of file descriptors or a connection aborted
+                    // before it was accepted, keep serving the others
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(error = %_error, "coordinator could not accept a connection");
+                    if _error.kind() != io::ErrorKind::ConnectionAborted {
+                        thread::sleep(ACCEPT_RETRY_DELAY);
+                    }
+                    continue;
+                }
+            };
+            let coordinator = Arc::clone(&self);
+            thread::spawn(move || {
+                // A client disconnecting abruptly only ends its connection
+                let _ = coordinator.handle(stream);
+            });
+        }
+        Ok(())
+    }
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        // Clients heartbeat every third of the lease time to live, those
+        // silent for longer have lost their lease
+        stream.set_read_timeout(Some(self.reissue_delay))?;
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let response = match self.respond(&line?) {
+                Ok(response) => response,
+                Err(message) => format!("ERROR {}", message),
+            };
+            writeln!(writer, "{}", response)?;
+        }
+        Ok(())
+    }
+    fn respond(&self, request: &str) -> Result<String, String> {
+        let words: Vec<&str> = request.split_whitespace().collect();
+        let invalid = || format!("invalid request '{}'", request);
+        let number = |index: usize| {
+            words
+                .get(index)
+                .and_then(|word| word.parse::<u64>().ok())
+                .ok_or_else(invalid)
+        };
+        let node_id = || u16::try_from(number(1)?).map_err(|_| invalid());
+        let ttl_ms = self.lease_ttl.as_millis();
+        match words.first().copied() {
+            Some("ACQUIRE") => {
+                let node_id_bits = u8::try_from(number(1)?).map_err(|_| invalid())?;
+                let (node_id, token) = self.acquire(node_id_bits.min(self.node_id_bits))?;
+                Ok(format!("LEASE {} {} {}", node_id, token, ttl_ms))
+            }
+            Some("HEARTBEAT") => {
+                self.heartbeat(node_id()?, number(2)?)?;
+                Ok(format!("OK {}", ttl_ms))
+            }
+            Some("RELEASE") => {
+                self.release(node_id()?, number(2)?);
+                Ok("OK 0".to_owned())
+            }
+            _ => Err(format!("unknown request '{}'", request)),
+        }
+    }
+    /// Lowest node ID whose previous holder stopped at least `reissue_delay`
+    /// ago.
+    fn acquire(&self, node_id_bits: u8) -> Result<(u16, u64), String> {
+        let now = Instant::now();
+        let started_for = now.duration_since(self.started);
+        if started_for < self.reissue_delay {
+            return Err(format!(
+                "coordinator started recently, retry in {} ms",
+                (self.reissue_delay - started_for).as_millis()
+            ));
+        }
+        let mut leases = self.leases.lock().unwrap();
+        let max_node_id = (1_u32 << node_id_bits) - 1;
+        let node_id = (0..=max_node_id)
+            .map(|node_id| node_id as u16)
+            .find(|node_id| match leases.get(node_id) {
+                Some(lease) => now.duration_since(lease.last_heartbeat) >= self.reissue_delay,
+                None => true,
+            })
+            .ok_or_else(|| format!("all {} node IDs are leased", max_node_id + 1))?;
+        let mut hasher = self.token_hasher.build_hasher();
+        hasher.write_u64(self.next_token.fetch_add(1, Ordering::Relaxed));
+        // 0 is never handed out, so it matches no lease
+        let token = hasher.finish().max(1);
+        leases.insert(
+            node_id,
+            Lease {
+                token,
+                last_heartbeat: now,
+                released: false,
+            },
+        );
+        Ok((node_id, token))
+    }
+    fn heartbeat(&self, node_id: u16, token: u64) -> Result<(), String> {
+        let now = Instant::now();
+        let mut leases = self.leases.lock().unwrap();
+        match leases.get_mut(&node_id) {
+            Some(lease) if token != 0 && lease.token == token && !lease.released => {
+                if now.duration_since(lease.last_heartbeat) > self.lease_ttl {
+                    return Err("lease expired".to_owned());
+                }
+                lease.last_heartbeat = now;
+                Ok(())
+            }
+            _ => Err("unknown lease".to_owned()),
+        }
+    }
+    /// The node ID is still reissued only `reissue_delay` after the last
+    /// heartbeat.
+    fn release(&self, node_id: u16, token: u64) {
+        let mut leases = self.leases.lock().unwrap();
+        if let Some(lease) = leases.get_mut(&node_id) {
+            if lease.token == token {
+                lease.released = true;
+            }
+        }
+    }
+}
+
+/// Node ID leased from a `Coordinator`, kept alive by a background thread
+/// sending heartbeats every third of the lease time to live. It is valid
+/// until `lease_ttl` after the last heartbeat sent and answered, and
+/// released when dropped. Requests to the coordinator time out after the
+/// heartbeat interval, which bounds how long dropping the lease waits for the
+/// heartbeat thread.
+#[derive(Debug)]
+pub struct CoordinatorLease {
+    node_id: u16,
+    started: Instant,
+    /// Microseconds since `started` the lease is valid for
+    valid_until_micros: Arc<AtomicU64>,
+    stop: Option<Sender<()>>,
+    heartbeats: Option<JoinHandle<()>>,
+}
+
+impl CoordinatorLease {
+    /// Lease a node ID that fits in `node_id_bits` from the coordinator
+    /// listening at `address`.
+    pub fn acquire(address: impl ToSocketAddrs, node_id_bits: u8) -> io::Result<Self> {
+        let mut last_error = None;
+        let mut connected = None;
+        for address in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, ACQUIRE_TIMEOUT) {
+                Ok(stream) => {
+                    connected = Some(stream);
+                    break;
+                }
+                Err(error) => last_error = Some(error),
+            }
+        }
+        let stream = connected.ok_or_else(|| {
+            last_error.unwrap_or_else(|| invalid_data("address resolved to no socket address"))
+        })?;
+        stream.set_read_timeout(Some(ACQUIRE_TIMEOUT))?;
+        stream.set_write_timeout(Some(ACQUIRE_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        let started = Instant::now();
+        let response = request(
+            &mut reader,
+            &mut writer,
+            &format!("ACQUIRE {}", node_id_bits),
+        )?;
+        let (node_id, token, ttl) = match response.split_whitespace().collect::<Vec<&str>>()[..] {
+            ["LEASE", node_id, token, ttl_ms] => (
+                node_id.parse::<u16>().map_err(invalid_data)?,
+                token.parse::<u64>().map_err(invalid_data)?,
+                Duration::from_millis(ttl_ms.parse::<u64>().map_err(invalid_data)?),
+            ),
+            _ => return Err(invalid_data(response)),
+        };
+        if node_id_bits < 16 && node_id >> node_id_bits != 0 {
+            return Err(invalid_data(format!(
+                "node ID '{}' does not fit in {} bits",
+                node_id, node_id_bits
+            )));
+        }
+        // An answer later than the next heartbeat is of no use
+        let interval = (ttl / 3).max(Duration::from_millis(1));
+        writer.set_read_timeout(Some(interval))?;
+        writer.set_write_timeout(Some(interval))?;
+        let valid_until_micros = Arc::new(AtomicU64::new(ttl.as_micros() as u64));
+        let (stop, stopped) = mpsc::channel::<()>();
+        let valid_until = Arc::clone(&valid_until_micros);
+        let heartbeats = thread::spawn(move || loop {
+            match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => {}
+                // Dropped
+                _ => {
+                    let release = format!("RELEASE {} {}", node_id, token);
+                    let _ = request(&mut reader, &mut writer, &release);
+                    return;
+                }
+            }
+            let sent = started.elapsed();
+            let heartbeat = format!("HEARTBEAT {} {}", node_id, token);
+            match request(&mut reader, &mut writer, &heartbeat) {
+                Ok(response) if response.starts_with("OK ") => {
+                    valid_until.store((sent + ttl).as_micros() as u64, Ordering::Relaxed);
+                }
+                // Lost, stop generating right away
+                _ => {
+                    valid_until.store(0, Ordering::Relaxed);
+                    return;
+                }
+            }
+        });
+        Ok(CoordinatorLease {
+            node_id,
+            started,
+            valid_until_micros,
+            stop: Some(stop),
+            heartbeats: Some(heartbeats),
+        })
+    }
+    pub fn node_id(&self) -> u16 {
+        self.node_id
+    }
+    /// Whether IDs may still be generated with this node ID.
+    pub fn is_valid(&self) -> bool {
+        (self.started.elapsed().as_micros() as u64)
+            < self.valid_until_micros.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for CoordinatorLease {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(heartbeats) = self.heartbeats.take() {
+            let _ = heartbeats.join();
+        }
+    }
+}
+
+fn request(
+    reader: &mut BufReader<TcpStream>,
+    writer: &mut TcpStream,
+    line: &str,
+) -> io::Result<String> {
+    writeln!(writer, "{}", line)?;
+    let mut response = String::new();
+    if reader.read_line(&mut response)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "coordinator closed the connection",
+        ));
+    }
+    Ok(response.trim().to_owned())
+}
+
+fn invalid_data(message: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn lease_from_coordinator() {
+        use super::*;
+        let coordinator = Arc::new(Coordinator::new(
+            2,
+            Duration::from_millis(150),
+            Duration::from_millis(200),
+        ));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || coordinator.serve(listener));
+        // Refused while clients of a previous run may reclaim their node IDs
+        let error = CoordinatorLease::acquire(address, 9).unwrap_err();
+        assert!(error.to_string().contains("retry"), "{}", error);
+        thread::sleep(Duration::from_millis(200));
+        let first = CoordinatorLease::acquire(address, 9).unwrap();
+        let second = CoordinatorLease::acquire(address, 1).unwrap();
+        assert_eq!((first.node_id(), second.node_id()), (0, 1));
+        assert!(CoordinatorLease::acquire(address, 1).is_err());
+        // Kept alive by heartbeats
+        thread::sleep(Duration::from_millis(300));
+        assert!(first.is_valid());
+        drop(first);
+        // Released, but not reissued before the reissue delay
+        assert_eq!(CoordinatorLease::acquire(address, 9).unwrap().node_id(), 2);
+        thread::sleep(Duration::from_millis(250));
+        assert_eq!(CoordinatorLease::acquire(address, 9).unwrap().node_id(), 0);
+        // Out of range numbers are rejected instead of truncated
+        let stream = TcpStream::connect(address).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        for line in ["HEARTBEAT 65536 1", "RELEASE 65536 1", "ACQUIRE 265"] {
+            let response = request(&mut reader, &mut writer, line).unwrap();
+            assert!(
+                response.starts_with("ERROR invalid request"),
+                "{}",
+                response
+            );
+        }
+        // Released leases are not renewed
+        let response = request(&mut reader, &mut writer, "HEARTBEAT 0 0").unwrap();
+        assert_eq!(response, "ERROR unknown lease");
+        assert!(matches!(
+            Coordinator::try_new(9, Duration::from_secs(1), Duration::from_millis(100)),
+            Err(SequenceGeneratorError::InvalidConfiguration(_))
+        ));
+    }
+}
@@ -0,0 +1,178 @@
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// What `SequenceProperties::set_high_water_mark` does when the system clock
+/// is behind the persisted mark, e.g. after a restart with a regressed clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupPolicy {
+    /// Fail with `SequenceGeneratorError::ClockBehindHighWaterMark`.
+    Refuse,
+    /// Sleep until the clock passes the mark.
+    Wait,
+}
+
+/// Last issued timestamp persisted to a local state file, so that a node
+/// restarting with its clock behind the time it last used does not reissue
+/// IDs. The file holds the microseconds since the UNIX epoch after the last
+/// issued tick, which is independent of the layout.
+///
+/// Every tick used for the first time is written to disk before any ID with
+/// it is returned. A `lease` writes a mark that far ahead instead, so that
+/// only one write per lease is needed, at the cost of waiting or refusing
+/// for up to `lease` on every restart.
+#[derive(Debug)]
+pub struct HighWaterMark {
+    pub path: PathBuf,
+    pub lease: Duration,
+    pub policy: StartupPolicy,
+    persisted_micros: Cell<Option<u64>>,
+}
+
+impl HighWaterMark {
+    pub fn new(path: impl Into<PathBuf>, lease: Duration, policy: StartupPolicy) -> Self {
+        HighWaterMark {
+            path: path.into(),
+            lease,
+            policy,
+            persisted_micros: Cell::new(None),
+        }
+    }
+    /// Mark in the state file, `None` if the file does not exist yet.
+    pub fn load(&self) -> io::Result<Option<u64>> {
+        let micros = read_mark(&self.path)?;
+        self.persisted_micros.set(micros);
+        Ok(micros)
+    }
+    /// Last mark read or written.
+    pub fn persisted_micros(&self) -> Option<u64> {
+        self.persisted_micros.get()
+    }
+    /// Persist the end of a tick about to be used, plus the lease, unless a
+    /// later mark is already persisted.
+    pub(crate) fn advance(&self, tick_end_micros: u64) -> io::Result<()> {
+        if self
+            .persisted_micros
+            .get()
+            .map_or(false, |persisted| persisted >= tick_end_micros)
+        {
+            return Ok(());
+        }
+        let micros = tick_end_micros.saturating_add(self.lease.as_micros() as u64);
+        write_mark(&self.path, micros)?;
+        self.persisted_micros.set(Some(micros));
+        Ok(())
+    }
+}
+
+fn read_mark(path: &Path) -> io::Result<Option<u64>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => contents.trim().parse::<u64>().map(Some).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "high-water mark '{}' in '{}' is not an unsigned integer",
+                    contents.trim(),
+                    path.display()
+                ),
+            )
+        }),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Write to a temporary file in the same directory, fsync it and rename it
+/// over `path`, so that a crash leaves either the old or the new mark.
+fn write_mark(path: &Path, micros: u64) -> io::Result<()> {
+    let mut temporary_path = path.as_os_str().to_owned();
+    temporary_path.push(".tmp");
+    let temporary_path = PathBuf::from(temporary_path);
+    let mut file = File::create(&temporary_path)?;
+    writeln!(file, "{}", micros)?;
+    file.sync_all()?;
+    fs::rename(&temporary_path, path)?;
+    // Persist the rename itself
+    #[cfg(unix)]
+    {
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(directory)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn persist_and_reload() {
+        use super::*;
+        use crate::sequence_generator::{generate_id, SequenceGeneratorError, SequenceProperties};
+        use std::time::{SystemTime, UNIX_EPOCH};
+        let path = std::env::temp_dir().join(format!("high-water-mark-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let new_properties = || {
+            SequenceProperties::new(
+                UNIX_EPOCH + Duration::from_secs(1_577_836_800),
+                9,
+                0,
+                11,
+                2,
+                0,
+                1000,
+            )
+        };
+        let now_micros = || {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_micros() as u64
+        };
+        // First start without a state file
+        let mut properties = new_properties();
+        properties
+            .set_high_water_mark(HighWaterMark::new(
+                &path,
+                Duration::from_millis(200),
+                StartupPolicy::Refuse,
+            ))
+            .unwrap();
+        generate_id(&properties).unwrap();
+        let mark = read_mark(&path).unwrap().unwrap();
+        assert!(mark >= now_micros() + 100_000);
+        // Restart within the lease
+        let mut properties = new_properties();
+        let error = properties
+            .set_high_water_mark(HighWaterMark::new(
+                &path,
+                Duration::ZERO,
+                StartupPolicy::Refuse,
+            ))
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            SequenceGeneratorError::ClockBehindHighWaterMark { .. }
+        ));
+        properties
+            .set_high_water_mark(HighWaterMark::new(
+                &path,
+                Duration::ZERO,
+                StartupPolicy::Wait,
+            ))
+            .unwrap();
+        assert!(now_micros() >= mark);
+        generate_id(&properties).unwrap();
+        assert!(read_mark(&path).unwrap().unwrap() > mark);
+        fs::write(&path, "garbage").unwrap();
+        assert!(
+            HighWaterMark::new(&path, Duration::ZERO, StartupPolicy::Wait)
+                .load()
+                .is_err()
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
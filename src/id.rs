@@ -0,0 +1,143 @@
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+/// Generated ID, serialized as a number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct SequenceId(pub u64);
+
+/// Generated ID, serialized as a decimal string. JavaScript numbers lose
+/// precision above 2^53, so prefer this one for IDs returned to browsers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct SequenceIdString(pub u64);
+
+macro_rules! impl_sequence_id {
+    ($name:ident) => {
+        impl From<u64> for $name {
+            fn from(id: u64) -> Self {
+                $name(id)
+            }
+        }
+
+        impl From<$name> for u64 {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ParseIntError;
+
+            fn from_str(value: &str) -> Result<Self, Self::Err> {
+                value.trim().parse::<u64>().map($name)
+            }
+        }
+    };
+}
+
+impl_sequence_id!(SequenceId);
+impl_sequence_id!(SequenceIdString);
+
+impl From<SequenceId> for SequenceIdString {
+    fn from(id: SequenceId) -> Self {
+        SequenceIdString(id.0)
+    }
+}
+
+impl From<SequenceIdString> for SequenceId {
+    fn from(id: SequenceIdString) -> Self {
+        SequenceId(id.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serialization {
+    use super::{SequenceId, SequenceIdString};
+    use serde::de::{self, Deserializer, Visitor};
+    use serde::{Deserialize, Serialize, Serializer};
+    use std::convert::TryFrom;
+    use std::fmt;
+
+    /// Both newtypes accept either a number or a decimal string.
+    struct IdVisitor;
+
+    impl<'de> Visitor<'de> for IdVisitor {
+        type Value = u64;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("an unsigned 64-bit ID as a number or a decimal string")
+        }
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<u64, E> {
+            Ok(value)
+        }
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<u64, E> {
+            u64::try_from(value).map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self))
+        }
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<u64, E> {
+            value
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+        }
+    }
+
+    impl Serialize for SequenceId {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_u64(self.0)
+        }
+    }
+
+    impl Serialize for SequenceIdString {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(&self.0)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for SequenceId {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(IdVisitor).map(SequenceId)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for SequenceIdString {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer
+                .deserialize_any(IdVisitor)
+                .map(SequenceIdString)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    #[test]
+    fn serde_number_or_string() {
+        use super::*;
+        let id = 731536357192630777_u64;
+        assert_eq!(
+            serde_json::to_string(&SequenceId(id)).unwrap(),
+            "731536357192630777"
+        );
+        assert_eq!(
+            serde_json::to_string(&SequenceIdString(id)).unwrap(),
+            "\"731536357192630777\""
+        );
+        for json in ["731536357192630777", "\"731536357192630777\""] {
+            assert_eq!(
+                serde_json::from_str::<SequenceId>(json).unwrap(),
+                SequenceId(id)
+            );
+            assert_eq!(
+                serde_json::from_str::<SequenceIdString>(json).unwrap(),
+                SequenceIdString(id)
+            );
+        }
+        assert!(serde_json::from_str::<SequenceId>("-1").is_err());
+    }
+}
@@ -0,0 +1,470 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Component stored in a group of bits of the ID. Fields with any name other
+/// than `timestamp`, `sequence`, `node_id`, `checksum` or `version` are `Extra` fields,
+/// whose value is fixed per generator or given on each call (e.g. an entity
+/// type tag).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FieldKind {
+    Timestamp,
+    Sequence,
+    NodeId,
+    Checksum,
+    /// Layout version, selecting the layout used to decode the ID.
+    Version,
+    Extra,
+}
+
+impl FieldKind {
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "timestamp" => FieldKind::Timestamp,
+            "sequence" => FieldKind::Sequence,
+            "node_id" => FieldKind::NodeId,
+            "checksum" => FieldKind::Checksum,
+            "version" => FieldKind::Version,
+            _ => FieldKind::Extra,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            FieldKind::Timestamp => "timestamp",
+            FieldKind::Sequence => "sequence",
+            FieldKind::NodeId => "node_id",
+            FieldKind::Checksum => "checksum",
+            FieldKind::Version => "version",
+            FieldKind::Extra => "extra",
+        }
+    }
+}
+
+impl fmt::Display for FieldKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for FieldKind {
+    type Err = String;
+
+    /// Built-in fields only, extra fields are known by their name.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "timestamp" => Ok(FieldKind::Timestamp),
+            "sequence" => Ok(FieldKind::Sequence),
+            "node_id" | "node-id" | "node" => Ok(FieldKind::NodeId),
+            "checksum" => Ok(FieldKind::Checksum),
+            "version" => Ok(FieldKind::Version),
+            _ => Err(format!(
+                "ERROR: Unknown field '{}'. Expected one of 'timestamp', 'sequence', 'node_id', 'checksum' or 'version'",
+                value
+            )),
+        }
+    }
+}
+
+/// Position of a field inside the ID: `bits` wide, starting `shift` bits
+/// from the right-most (least significant) bit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    pub kind: FieldKind,
+    pub bits: u8,
+    pub shift: u8,
+}
+
+impl Field {
+    pub fn max_value(&self) -> u64 {
+        if self.bits >= 64 {
+            u64::MAX
+        } else {
+            (1_u64 << self.bits) - 1
+        }
+    }
+    pub fn encode(&self, value: u64) -> u64 {
+        (value & self.max_value()) << self.shift
+    }
+    pub fn decode(&self, id: u64) -> u64 {
+        (id >> self.shift) & self.max_value()
+    }
+}
+
+/// Ordered description of the bit groups of an ID. Both ID generation and
+/// decoding are driven by it, so changing the order of the fields here is
+/// enough to change the structure of the generated IDs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdLayout {
+    unused_bits: u8,
+    fields: Vec<Field>,
+}
+
+impl IdLayout {
+    /// Fields are listed from the left-most (most significant) to the
+    /// right-most bits. The timestamp must be the left-most field so that IDs
+    /// remain sortable by generation time, and together with the unused bits
+    /// the fields must fill all 64 bits. Extra fields need a name, see
+    /// `IdLayout::named`.
+    pub fn new(unused_bits: u8, fields: &[(FieldKind, u8)]) -> Self {
+        let fields: Vec<(&str, u8)> = fields
+            .iter()
+            .map(|(kind, bits)| (kind.name(), *bits))
+            .collect();
+        IdLayout::named(unused_bits, &fields)
+    }
+    /// Fields are listed by name from the left-most (most significant) to the
+    /// right-most bits. The `timestamp`, `sequence` and `node_id` fields are
+    /// required and any other name is an extra field. The timestamp must be
+    /// the left-most field so that IDs remain sortable by generation time,
+    /// preceded only by the optional `version` field, and together with the
+    /// unused bits the fields must fill all 64 bits.
+    pub fn named(unused_bits: u8, fields: &[(&str, u8)]) -> Self {
+        IdLayout::try_named(unused_bits, fields).unwrap_or_else(|error| panic!("{}", error))
+    }
+    /// `IdLayout::named` returning an invalid layout as an error.
+    pub fn try_named(unused_bits: u8, fields: &[(&str, u8)]) -> Result<Self, String> {
+        for kind in [FieldKind::Timestamp, FieldKind::Sequence, FieldKind::NodeId] {
+            if !fields.iter().any(|(name, _)| *name == kind.name()) {
+                return Err(format!("ERROR: Layout is missing the '{}' field.", kind));
+            }
+        }
+        let version_position = fields
+            .iter()
+            .position(|(name, _)| *name == FieldKind::Version.name());
+        if version_position.unwrap_or(0) != 0 {
+            return Err("ERROR: Layout field 'version' must be the left-most field.".to_owned());
+        }
+        let timestamp_position = version_position.map_or(0, |_| 1);
+        if fields[timestamp_position].0 != FieldKind::Timestamp.name() {
+            return Err(format!(
+                "ERROR: Layout field '{}' cannot be placed before the timestamp.",
+                fields[timestamp_position].0
+            ));
+        }
+        let total_bits = fields
+            .iter()
+            .fold(unused_bits as u32, |total, (_, bits)| total + *bits as u32);
+        if total_bits != 64 {
+            return Err(format!(
+                "ERROR: Sum of layout bits '{}' must be exactly 64, including '{}' unused bits.",
+                total_bits, unused_bits
+            ));
+        }
+        let mut shift = 64_u8 - unused_bits;
+        let fields = fields
+            .iter()
+            .enumerate()
+            .map(|(index, (name, bits))| {
+                if name.is_empty() {
+                    return Err("ERROR: Layout field names cannot be empty.".to_owned());
+                }
+                if fields[..index].iter().any(|(other, _)| other == name) {
+                    return Err(format!("ERROR: Layout field '{}' is repeated.", name));
+                }
+                if *bits == 0 {
+                    return Err(format!(
+                        "ERROR: Layout field '{}' must be at least 1 bit wide.",
+                        name
+                    ));
+                }
+                shift -= bits;
+                Ok(Field {
+                    name: name.to_string(),
+                    kind: FieldKind::from_name(name),
+                    bits: *bits,
+                    shift,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(IdLayout {
+            unused_bits,
+            fields,
+        })
+    }
+    pub fn unused_bits(&self) -> u8 {
+        self.unused_bits
+    }
+    /// Fields from the left-most to the right-most bits.
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+    /// First field of the given kind. Use `field_named` for extra fields.
+    pub fn field(&self, kind: FieldKind) -> Option<&Field> {
+        self.fields.iter().find(|field| field.kind == kind)
+    }
+    pub fn field_named(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+    /// Field widths and names as accepted by `IdLayout::named`.
+    pub fn specs(&self) -> Vec<(&str, u8)> {
+        self.fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.bits))
+            .collect()
+    }
+    /// Same field widths arranged in a different order. The fields listed
+    /// in `order` are arranged, from left-most to right-most, in the positions
+    /// they take up, and the others keep their position: unless listed, the
+    /// version and the timestamp stay the left-most fields, the checksum the
+    /// right-most field and extra fields where they are.
+    pub fn reordered(&self, order: &[&str]) -> Self {
+        self.try_reordered(order)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+    /// `IdLayout::reordered` returning an unknown field as an error.
+    pub fn try_reordered(&self, order: &[&str]) -> Result<Self, String> {
+        let mut positions = order
+            .iter()
+            .map(|name| {
+                self.fields
+                    .iter()
+                    .position(|field| field.name == *name)
+                    .ok_or_else(|| format!("ERROR: Layout is missing the '{}' field.", name))
+            })
+            .collect::<Result<Vec<usize>, String>>()?;
+        positions.sort_unstable();
+        let mut fields = self.specs();
+        for (position, name) in positions.into_iter().zip(order) {
+            fields[position] = (name, self.field_named(name).unwrap().bits);
+        }
+        IdLayout::try_named(self.unused_bits, &fields)
+    }
+}
+
+/// Named group of bits inside the node ID, such as a datacenter or worker
+/// identifier. `shift` is counted from the right-most bit of the node ID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeSubfield {
+    pub name: String,
+    pub bits: u8,
+    pub shift: u8,
+}
+
+impl NodeSubfield {
+    pub fn max_value(&self) -> u16 {
+        ((1_u32 << self.bits) - 1) as u16
+    }
+}
+
+/// Split of the node ID into named subfields, listed from the left-most to
+/// the right-most bits, e.g. `[("datacenter", 5), ("worker", 5)]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeIdLayout {
+    subfields: Vec<NodeSubfield>,
+}
+
+impl NodeIdLayout {
+    pub fn new(node_id_bits: u8, subfields: &[(&str, u8)]) -> Self {
+        NodeIdLayout::try_new(node_id_bits, subfields).unwrap_or_else(|error| panic!("{}", error))
+    }
+    /// `NodeIdLayout::new` returning invalid subfields as an error.
+    pub fn try_new(node_id_bits: u8, subfields: &[(&str, u8)]) -> Result<Self, String> {
+        let total_bits = subfields
+            .iter()
+            .fold(0_u32, |total, (_, bits)| total + *bits as u32);
+        if total_bits != node_id_bits as u32 {
+            return Err(format!(
+                "ERROR: Sum of node ID subfield bits '{}' must match node_id_bits '{}'.",
+                total_bits, node_id_bits
+            ));
+        }
+        let mut shift = node_id_bits;
+        let subfields = subfields
+            .iter()
+            .enumerate()
+            .map(|(index, (name, bits))| {
+                if name.is_empty() {
+                    return Err("ERROR: Node ID subfield names cannot be empty.".to_owned());
+                }
+                if subfields[..index].iter().any(|(other, _)| other == name) {
+                    return Err(format!("ERROR: Node ID subfield '{}' is repeated.", name));
+                }
+                if *bits == 0 {
+                    return Err(format!(
+                        "ERROR: Node ID subfield '{}' must be at least 1 bit wide.",
+                        name
+                    ));
+                }
+                shift -= bits;
+                Ok(NodeSubfield {
+                    name: name.to_string(),
+                    bits: *bits,
+                    shift,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(NodeIdLayout { subfields })
+    }
+    /// Subfields from the left-most to the right-most bits.
+    pub fn subfields(&self) -> &[NodeSubfield] {
+        &self.subfields
+    }
+    pub fn subfield(&self, name: &str) -> Option<&NodeSubfield> {
+        self.subfields.iter().find(|subfield| subfield.name == name)
+    }
+    /// Replace the value of subfield `name` inside `node_id`.
+    pub fn set(&self, node_id: u16, name: &str, value: u16) -> Result<u16, String> {
+        let subfield = self
+            .subfield(name)
+            .ok_or_else(|| format!("ERROR: Unknown node ID subfield '{}'.", name))?;
+        if value > subfield.max_value() {
+            return Err(format!(
+                "ERROR: Node ID subfield '{}' value '{}' does not fit in {} bits (maximum {}).",
+                name,
+                value,
+                subfield.bits,
+                subfield.max_value()
+            ));
+        }
+        let mask = (subfield.max_value() as u32) << subfield.shift;
+        Ok(((node_id as u32 & !mask) | ((value as u32) << subfield.shift)) as u16)
+    }
+    pub fn get(&self, node_id: u16, name: &str) -> Option<u16> {
+        self.subfield(name)
+            .map(|subfield| (node_id >> subfield.shift) & subfield.max_value())
+    }
+    /// Values of every subfield of `node_id`, from left-most to right-most.
+    pub fn split(&self, node_id: u16) -> Vec<(String, u16)> {
+        self.subfields
+            .iter()
+            .map(|subfield| {
+                (
+                    subfield.name.clone(),
+                    (node_id >> subfield.shift) & subfield.max_value(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Parse a comma separated field order such as `node_id,sequence`, naming
+/// fields of `layout`. `node` and `node-id` are accepted as aliases of
+/// `node_id`.
+pub fn parse_field_order(value: &str, layout: &IdLayout) -> Result<Vec<String>, String> {
+    let mut names: Vec<String> = Vec::new();
+    for name in value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        let name = match name.parse::<FieldKind>() {
+            Ok(kind) => kind.name(),
+            Err(_) => name,
+        };
+        if layout.field_named(name).is_none() {
+            return Err(format!(
+                "ERROR: Unknown field '{}'. Expected one of '{}'",
+                name,
+                layout
+                    .fields()
+                    .iter()
+                    .map(|field| field.name.as_str())
+                    .collect::<Vec<&str>>()
+                    .join("', '")
+            ));
+        }
+        if names.iter().any(|other| other == name) {
+            return Err(format!("ERROR: Field '{}' is repeated.", name));
+        }
+        names.push(name.to_owned());
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn encode_decode() {
+        // Twitter-like structure with the sequence on the right-most bits
+        use super::*;
+        let layout = IdLayout::new(
+            1,
+            &[
+                (FieldKind::Timestamp, 41),
+                (FieldKind::NodeId, 10),
+                (FieldKind::Sequence, 12),
+            ],
+        );
+        let timestamp = layout.field(FieldKind::Timestamp).unwrap();
+        let node_id = layout.field(FieldKind::NodeId).unwrap();
+        let sequence = layout.field(FieldKind::Sequence).unwrap();
+        assert_eq!(
+            (timestamp.shift, node_id.shift, sequence.shift),
+            (22, 12, 0)
+        );
+        let id = timestamp.encode(1_000_000) | node_id.encode(1023) | sequence.encode(5);
+        assert_eq!(id, (1_000_000 << 22) | (1023 << 12) | 5);
+        assert_eq!(timestamp.decode(id), 1_000_000);
+        assert_eq!(node_id.decode(id), 1023);
+        assert_eq!(sequence.decode(id), 5);
+        // Values wider than the field must not leak into neighbouring bits
+        assert_eq!(sequence.encode(4096 + 7), 7);
+        let field_order = parse_field_order("sequence,node", &layout).unwrap();
+        let field_order: Vec<&str> = field_order.iter().map(String::as_str).collect();
+        let reordered = layout.reordered(&field_order);
+        assert_eq!(reordered.field(FieldKind::Sequence).unwrap().shift, 10);
+        assert_eq!(reordered.field(FieldKind::NodeId).unwrap().shift, 0);
+        assert_eq!(reordered.field(FieldKind::Timestamp).unwrap().shift, 22);
+        assert!(parse_field_order("sequence,nodeid", &layout).is_err());
+        assert!(parse_field_order("sequence,node_id,sequence", &layout).is_err());
+    }
+
+    #[test]
+    fn extra_fields() {
+        use super::*;
+        let layout = IdLayout::named(
+            0,
+            &[
+                ("timestamp", 40),
+                ("type", 4),
+                ("sequence", 11),
+                ("node_id", 9),
+            ],
+        );
+        let entity_type = layout.field_named("type").unwrap();
+        assert_eq!(
+            (entity_type.kind, entity_type.shift),
+            (FieldKind::Extra, 20)
+        );
+        let reordered = layout.reordered(&["sequence", "node_id", "type"]);
+        assert_eq!(reordered.field_named("type").unwrap().shift, 0);
+        assert_eq!(reordered.field(FieldKind::Timestamp).unwrap().shift, 24);
+        // Fields left out of the order keep their position
+        let reordered = layout.reordered(&["node_id", "sequence"]);
+        assert_eq!(reordered.field_named("type").unwrap().shift, 20);
+        assert_eq!(reordered.field(FieldKind::Sequence).unwrap().shift, 0);
+    }
+
+    #[test]
+    fn node_subfields() {
+        use super::*;
+        let node_layout = NodeIdLayout::new(10, &[("datacenter", 5), ("worker", 5)]);
+        let node_id = node_layout.set(0, "datacenter", 3).unwrap();
+        let node_id = node_layout.set(node_id, "worker", 17).unwrap();
+        assert_eq!(node_id, (3 << 5) | 17);
+        assert_eq!(node_layout.get(node_id, "datacenter"), Some(3));
+        assert_eq!(
+            node_layout.split(node_id),
+            vec![("datacenter".to_owned(), 3), ("worker".to_owned(), 17)]
+        );
+        // Overwriting a subfield leaves the others untouched
+        let node_id = node_layout.set(node_id, "datacenter", 31).unwrap();
+        assert_eq!(node_layout.get(node_id, "worker"), Some(17));
+        assert!(node_layout.set(node_id, "worker", 32).is_err());
+        assert!(node_layout.set(node_id, "process", 1).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot be placed before the timestamp")]
+    fn timestamp_first() {
+        use super::*;
+        IdLayout::new(
+            0,
+            &[
+                (FieldKind::Sequence, 12),
+                (FieldKind::Timestamp, 42),
+                (FieldKind::NodeId, 10),
+            ],
+        );
+    }
+}
@@ -1 +1,16 @@
+pub mod checksum;
+pub mod client;
+pub mod config;
+pub mod coordinator;
+pub mod high_water_mark;
+pub mod id;
+pub mod layout;
+pub mod metrics;
+pub mod node_id;
+#[cfg(unix)]
+pub mod node_id_lease;
+pub mod observer;
+pub mod registry;
 pub mod sequence_generator;
+#[cfg(unix)]
+pub mod unix_socket;
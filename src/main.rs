@@ -1,11 +1,14 @@
+mod cli;
+
 use ::sequence_generator::*;
 use clap::Parser;
+use cli::error::CliError;
 use std::convert::TryFrom;
 use std::env;
+use std::io;
 use std::path::Path;
 use std::process;
-use std::rc::Rc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
 #[derive(clap::StructOpt, Debug)]
@@ -32,7 +35,7 @@ struct Opt {
     #[structopt(
         short = 'm',
         long = "--micros-ten-power",
-        help = "Exponent multiplier base 10 in microseconds for timestamp. [Default: 2 (operate in tenths of milliseconds)]"
+        help = "Exponent multiplier base 10 in microseconds for timestamp. [Default: 2 (operate in tenths of milliseconds). Maximum: 18]"
     )]
     micros_ten_power: Option<u8>,
     #[structopt(
@@ -53,6 +56,51 @@ struct Opt {
         help = "Numerical identifier for worker and datacenter information. [Default: 0]"
     )]
     node_id: Option<u16>,
+    #[structopt(
+        long = "--node-id-source",
+        help = "Derive the node ID instead of setting it: 'hostname' (trailing ordinal as in 'worker-17'), 'hostname-hash', 'env:NAME', or the host part of an interface address 'ipv4[:INTERFACE]', 'ipv6[:INTERFACE]', 'mac[:INTERFACE]'. Conflicts with --node-id"
+    )]
+    node_id_source: Option<String>,
+    #[structopt(
+        long = "--node-id-lease-dir",
+        help = "Lease the lowest node ID not used by other processes through lock files in this directory, shared by the processes of a host or over a network file system. Conflicts with --node-id"
+    )]
+    node_id_lease_dir: Option<String>,
+    #[structopt(
+        long = "--coordinator",
+        help = "Lease the node ID from the coordinator at this address, started with the 'coordinator' subcommand. Conflicts with --node-id"
+    )]
+    coordinator: Option<String>,
+    #[structopt(
+        long = "--datacenter-id",
+        help = "Datacenter subfield of the node ID. Requires --datacenter-id-bits. Conflicts with --node-id"
+    )]
+    datacenter_id: Option<u16>,
+    #[structopt(
+        long = "--datacenter-id-bits",
+        help = "Bits of the node ID used for the datacenter subfield, the left-most of the node ID."
+    )]
+    datacenter_id_bits: Option<u8>,
+    #[structopt(
+        long = "--worker-id",
+        help = "Worker subfield of the node ID. Requires --worker-id-bits. Conflicts with --node-id"
+    )]
+    worker_id: Option<u16>,
+    #[structopt(
+        long = "--worker-id-bits",
+        help = "Bits of the node ID used for the worker subfield, after the datacenter subfield."
+    )]
+    worker_id_bits: Option<u8>,
+    #[structopt(
+        long = "--process-id",
+        help = "Process subfield of the node ID. Requires --process-id-bits. Conflicts with --node-id"
+    )]
+    process_id: Option<u16>,
+    #[structopt(
+        long = "--process-id-bits",
+        help = "Bits of the node ID used for the process subfield, the right-most of the node ID."
+    )]
+    process_id_bits: Option<u8>,
     #[structopt(
         short = 'u',
         long = "--unused-bits",
@@ -64,6 +112,61 @@ struct Opt {
         help = "Unused (sign) bits at the left-most of the sequence ID. [Default: 0. Maximum: 8]"
     )]
     sign_bits: Option<u8>,
+    #[structopt(
+        long = "--checksum",
+        help = "Reserve the right-most bits for a checksum of the other fields to detect mistyped IDs: 'crc' or 'damm'. [Default: none]"
+    )]
+    checksum: Option<String>,
+    #[structopt(
+        long = "--checksum-bits",
+        help = "Bits used for the checksum, taken from the timestamp. [Default: 4. Maximum: 16. Minimum: 1 (crc), 4 (damm)]"
+    )]
+    checksum_bits: Option<u8>,
+    #[structopt(
+        long = "--field-order",
+        help = "Order of the fields after the timestamp, from left-most to right-most bits. [Default: 'sequence,node_id'. Twitter/Discord: 'node_id,sequence']"
+    )]
+    field_order: Option<String>,
+    #[structopt(
+        long = "--extra-field",
+        help = "Extra field 'name:bits[=value]' such as an entity type tag or shard number, placed after the timestamp and taking bits from it. Can be repeated."
+    )]
+    extra_field: Vec<String>,
+    #[structopt(
+        long = "--layout-version",
+        help = "Version stored in the left-most bits of every ID so that IDs remain decodable after the layout changes. Requires --layout-version-bits. [Default: none]"
+    )]
+    layout_version: Option<u64>,
+    #[structopt(
+        long = "--layout-version-bits",
+        help = "Bits used for the layout version, taken from the timestamp. [Maximum: 16. Minimum: 1]"
+    )]
+    layout_version_bits: Option<u8>,
+    #[structopt(
+        long = "--state-file",
+        help = "File persisting the last issued timestamp, so that a restart with the clock behind it does not reissue IDs. [Default: none]"
+    )]
+    state_file: Option<String>,
+    #[structopt(
+        long = "--state-lease-ms",
+        help = "Milliseconds the persisted timestamp is leased ahead, trading one write per lease for up to that delay on restart. [Default: 0]"
+    )]
+    state_lease_ms: Option<u64>,
+    #[structopt(
+        long = "--state-wait",
+        help = "On startup, wait until the clock passes the persisted timestamp instead of exiting with an error."
+    )]
+    state_wait: bool,
+    #[structopt(
+        long = "--config",
+        help = "TOML or JSON file describing the layout. Its values take precedence over the dotenv file and are overridden by command line parameters."
+    )]
+    config: Option<String>,
+    #[structopt(
+        long = "--preset",
+        help = "Base layout for the parameters not given otherwise: 'default', 'twitter', 'discord', 'instagram' or 'sonyflake'. [Default: 'default']"
+    )]
+    preset: Option<String>,
     #[structopt(
         default_value = ".env",
         long = "--dotenv-file",
@@ -75,96 +178,326 @@ struct Opt {
         help = "Initial time in nanoseconds for exponential backoff wait after sequence is exhausted. [Default: 1000]"
     )]
     cooldown_ns: Option<u64>,
+    #[structopt(
+        short = 'o',
+        long = "--output",
+        default_value = "indexed",
+        help = "Format of the generated IDs: 'plain' (one per line), 'indexed' ('index: id' lines), 'json' (array of decoded IDs), 'ndjson' (one decoded ID per line), 'csv' or 'binary' (8-byte big-endian)."
+    )]
+    output: cli::output::OutputFormat,
     #[structopt(
         short = 'd',
         long = "--debug",
-        help = "Show total time and time per generated ID in nanoseconds"
+        help = "Show total time and time per generated ID in nanoseconds on stderr"
     )]
     debug: bool,
     #[structopt(short = 'V', long = "--version", help = "Show release version number")]
     version: bool,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Print the minimum and maximum IDs generated during a time interval
+    Bounds(cli::bounds::BoundsOpt),
+    /// Hand out node IDs over TCP to generators started with --coordinator
+    Coordinator(cli::coordinator::CoordinatorOpt),
+    /// Print the timestamp, node ID, sequence and other fields of IDs
+    Decode(cli::decode::DecodeOpt),
+    /// Print the bit ranges, capacity and a bit diagram of the layout
+    Layout(cli::layout::LayoutOpt),
+    /// Serve IDs over HTTP from a single long-lived generator
+    Serve(cli::serve::ServeOpt),
 }
 
 fn main() {
+    if let Err(error) = run() {
+        eprintln!("ERROR: {}", error);
+        process::exit(error.exit_code());
+    }
+}
+
+fn run() -> Result<(), CliError> {
     let mut args = Opt::from_args();
+    if let Some(command) = args.command.take() {
+        // The read-only subcommands only need the layout
+        let mut layout = || build_properties(&mut args, true);
+        return match command {
+            Command::Bounds(options) => cli::bounds::run(layout()?, options),
+            Command::Coordinator(options) => cli::coordinator::run(options),
+            Command::Decode(options) => cli::decode::run(layout()?, options),
+            Command::Layout(options) => cli::layout::run(layout()?, options),
+            Command::Serve(options) => {
+                cli::serve::run(build_properties(&mut args, false)?, options)
+            }
+        };
+    }
+    if args.quantity.is_some() && args.number.is_some() {
+        return Err(CliError::usage(
+            "Conflicting parameters. Must only specify one of either '--quantity,-q' or '--number,-n'",
+        ));
+    }
+    let number = args.number.or(args.quantity).unwrap_or(1);
+    if number == 0 {
+        eprintln!("WARNING: No ids were requested.");
+    }
+    let properties = build_properties(&mut args, false)?;
+    let mut vector_ids: Vec<u64> = vec![0; number];
+    let time_now = Instant::now();
+    for element in vector_ids.iter_mut() {
+        *element = sequence_generator::generate_id(&properties)
+            .map_err(|error| CliError::generator("Failed to generate an ID", error))?;
+    }
+    let elapsed = time_now.elapsed().as_nanos();
+    let stdout = io::stdout();
+    match cli::output::write_ids(
+        &mut io::BufWriter::new(stdout.lock()),
+        args.output,
+        &vector_ids,
+        &properties,
+    ) {
+        // The reader, e.g. `head`, has all the IDs it wanted
+        Err(error) if error.kind() == io::ErrorKind::BrokenPipe => {}
+        result => {
+            result.map_err(|error| CliError::io(format!("Could not write the IDs: {}", error)))?
+        }
+    }
+    if args.debug && number > 0 {
+        eprintln!(
+            "It took {} nanoseconds, time per id: {:.2} ns",
+            elapsed,
+            elapsed as f64 / number as f64
+        );
+    }
+    Ok(())
+}
+
+/// Layout and node ID from the command line, configuration file, dotenv
+/// file and preset, in this order of precedence. With `layout_only`, as for
+/// the read-only subcommands, the node ID is not derived nor leased and the
+/// state file is not loaded.
+fn build_properties(
+    args: &mut Opt,
+    layout_only: bool,
+) -> Result<sequence_generator::SequenceProperties, CliError> {
+    let mut configured_node_subfields: Vec<config::FieldConfig> = Vec::new();
+    if let Some(config_file) = args.config.clone() {
+        cli::config_file::ConfigFile::load(Path::new(&config_file))
+            .map_err(|error| {
+                CliError::config(format!(
+                    "Invalid configuration file '{}': {}",
+                    config_file, error
+                ))
+            })?
+            .apply(args, &mut configured_node_subfields);
+    }
     let dotenv_file = &args.dotenv_file;
     if Path::new(dotenv_file).exists() {
-        dotenvy::from_filename(dotenv_file).unwrap_or_else(|_| {
-            panic!(
-                "ERROR: Could not retrieve environment variables from configuration file '{}'",
+        dotenvy::from_filename(dotenv_file).map_err(|_| {
+            CliError::config(format!(
+                "Could not retrieve environment variables from configuration file '{}'",
                 dotenv_file
-            )
-        });
+            ))
+        })?;
         for (key, value) in env::vars() {
+            if key == "PRESET" && !value.is_empty() && args.preset.is_none() {
+                args.preset = Some(value.clone());
+            }
             if key == "CUSTOM_EPOCH" && !value.is_empty() && args.custom_epoch.is_none() {
-                args.custom_epoch = Some(value.parse::<String>().unwrap_or_else(|_| {panic!(
-                    "ERROR: Couldn't parse value CUSTOM_EPOCH '{}' as String, invalid UTF-8 characters", value)
-                }));
+                args.custom_epoch = Some(value.parse::<String>().map_err(|_| CliError::config(format!("Couldn't parse value CUSTOM_EPOCH '{}' as String, invalid UTF-8 characters", value)))?);
             }
             if key == "NODE_ID_BITS" && !value.is_empty() && args.node_id_bits.is_none() {
-                args.node_id_bits = Some(value.parse::<u8>().unwrap_or_else(|_| {
-                    panic!(
-                    "ERROR: NODE_ID_BITS '{}' couldn't be interpreted as value between 1 and 16",
-                    value
-                )
-                }));
+                args.node_id_bits = Some(value.parse::<u8>().map_err(|_| {
+                    CliError::config(format!(
+                        "NODE_ID_BITS '{}' couldn't be interpreted as value between 1 and 16",
+                        value
+                    ))
+                })?);
             }
 
             if key == "SEQUENCE_BITS" && !value.is_empty() && args.sequence_bits.is_none() {
-                args.sequence_bits = Some(value.parse::<u8>().unwrap_or_else(|_| {
-                    panic!(
-                    "ERROR: SEQUENCE_BITS '{}' couldn't be interpreted as value between 1 and 16",
-                    value
-                )
-                }));
+                args.sequence_bits = Some(value.parse::<u8>().map_err(|_| {
+                    CliError::config(format!(
+                        "SEQUENCE_BITS '{}' couldn't be interpreted as value between 1 and 16",
+                        value
+                    ))
+                })?);
             }
             if key == "MICROS_TEN_POWER" && !value.is_empty() && args.micros_ten_power.is_none() {
-                args.micros_ten_power = Some(value.parse::<u8>().unwrap_or_else(|_| {panic!(
-                    "ERROR: MICROS_TEN_POWER '{}' couldn't be interpreted as value between 0 and 64", value)
-                }));
+                args.micros_ten_power = Some(value.parse::<u8>().map_err(|_| {
+                    CliError::config(format!(
+                        "MICROS_TEN_POWER '{}' couldn't be interpreted as value between 0 and 18",
+                        value
+                    ))
+                })?);
             }
             if key == "UNUSED_BITS" && !value.is_empty() && args.unused_bits.is_none() {
-                args.unused_bits = Some(value.parse::<u8>().unwrap_or_else(|_| {
-                    panic!(
-                        "ERROR: UNUSED_BITS '{}' couldn't be interpreted as value between 0 and 7",
+                args.unused_bits = Some(value.parse::<u8>().map_err(|_| {
+                    CliError::config(format!(
+                        "UNUSED_BITS '{}' couldn't be interpreted as value between 0 and 7",
                         value
-                    )
-                }));
+                    ))
+                })?);
             }
             if key == "SIGN_BITS" && !value.is_empty() && args.unused_bits.is_none() {
-                args.unused_bits = Some(value.parse::<u8>().unwrap_or_else(|_| {
-                    panic!(
-                        "ERROR: SIGN_BITS '{}' couldn't be interpreted as value between 0 and 7",
+                args.unused_bits = Some(value.parse::<u8>().map_err(|_| {
+                    CliError::config(format!(
+                        "SIGN_BITS '{}' couldn't be interpreted as value between 0 and 7",
+                        value
+                    ))
+                })?);
+            }
+            if key == "NODE_ID" && !value.is_empty() && args.node_id.is_none() {
+                args.node_id = Some(value.parse::<u16>().map_err(|_| {
+                    CliError::config(format!(
+                        "NODE_ID '{}' couldn't be interpreted as value between 0 and 65535",
+                        value
+                    ))
+                })?);
+            }
+            if key == "NODE_ID_SOURCE" && !value.is_empty() && args.node_id_source.is_none() {
+                args.node_id_source = Some(value.clone());
+            }
+            if key == "NODE_ID_LEASE_DIR" && !value.is_empty() && args.node_id_lease_dir.is_none() {
+                args.node_id_lease_dir = Some(value.clone());
+            }
+            if key == "COORDINATOR" && !value.is_empty() && args.coordinator.is_none() {
+                args.coordinator = Some(value.clone());
+            }
+            if key == "DATACENTER_ID" && !value.is_empty() && args.datacenter_id.is_none() {
+                args.datacenter_id = Some(value.parse::<u16>().map_err(|_| {
+                    CliError::config(format!(
+                        "DATACENTER_ID '{}' couldn't be interpreted as value between 0 and 65535",
+                        value
+                    ))
+                })?);
+            }
+            if key == "DATACENTER_ID_BITS" && !value.is_empty() && args.datacenter_id_bits.is_none()
+            {
+                args.datacenter_id_bits = Some(value.parse::<u8>().map_err(|_| {
+                    CliError::config(format!(
+                        "DATACENTER_ID_BITS '{}' couldn't be interpreted as value between 1 and 16",
+                        value
+                    ))
+                })?);
+            }
+            if key == "WORKER_ID" && !value.is_empty() && args.worker_id.is_none() {
+                args.worker_id = Some(value.parse::<u16>().map_err(|_| {
+                    CliError::config(format!(
+                        "WORKER_ID '{}' couldn't be interpreted as value between 0 and 65535",
+                        value
+                    ))
+                })?);
+            }
+            if key == "WORKER_ID_BITS" && !value.is_empty() && args.worker_id_bits.is_none() {
+                args.worker_id_bits = Some(value.parse::<u8>().map_err(|_| {
+                    CliError::config(format!(
+                        "WORKER_ID_BITS '{}' couldn't be interpreted as value between 1 and 16",
+                        value
+                    ))
+                })?);
+            }
+            if key == "PROCESS_ID" && !value.is_empty() && args.process_id.is_none() {
+                args.process_id = Some(value.parse::<u16>().map_err(|_| {
+                    CliError::config(format!(
+                        "PROCESS_ID '{}' couldn't be interpreted as value between 0 and 65535",
+                        value
+                    ))
+                })?);
+            }
+            if key == "PROCESS_ID_BITS" && !value.is_empty() && args.process_id_bits.is_none() {
+                args.process_id_bits = Some(value.parse::<u8>().map_err(|_| {
+                    CliError::config(format!(
+                        "PROCESS_ID_BITS '{}' couldn't be interpreted as value between 1 and 16",
+                        value
+                    ))
+                })?);
+            }
+            if key == "EXTRA_FIELDS" && !value.is_empty() && args.extra_field.is_empty() {
+                args.extra_field = value
+                    .split(',')
+                    .filter(|spec| !spec.trim().is_empty())
+                    .map(|spec| spec.trim().to_owned())
+                    .collect();
+            }
+            if key == "CHECKSUM" && !value.is_empty() && args.checksum.is_none() {
+                args.checksum = Some(value.clone());
+            }
+            if key == "CHECKSUM_BITS" && !value.is_empty() && args.checksum_bits.is_none() {
+                args.checksum_bits = Some(value.parse::<u8>().map_err(|_| {
+                    CliError::config(format!(
+                        "CHECKSUM_BITS '{}' couldn't be interpreted as value between 1 and 16",
+                        value
+                    ))
+                })?);
+            }
+            if key == "FIELD_ORDER" && !value.is_empty() && args.field_order.is_none() {
+                args.field_order = Some(value.clone());
+            }
+            if key == "LAYOUT_VERSION" && !value.is_empty() && args.layout_version.is_none() {
+                args.layout_version = Some(value.parse::<u64>().map_err(|_| {
+                    CliError::config(format!(
+                        "LAYOUT_VERSION '{}' couldn't be interpreted as an unsigned integer value",
+                        value
+                    ))
+                })?);
+            }
+            if key == "LAYOUT_VERSION_BITS"
+                && !value.is_empty()
+                && args.layout_version_bits.is_none()
+            {
+                args.layout_version_bits = Some(value.parse::<u8>().map_err(|_| CliError::config(format!("LAYOUT_VERSION_BITS '{}' couldn't be interpreted as value between 1 and 16",
+                        value)))?);
+            }
+            if key == "STATE_FILE" && !value.is_empty() && args.state_file.is_none() {
+                args.state_file = Some(value.clone());
+            }
+            if key == "STATE_LEASE_MS" && !value.is_empty() && args.state_lease_ms.is_none() {
+                args.state_lease_ms = Some(value.parse::<u64>().map_err(|_| {
+                    CliError::config(format!(
+                        "STATE_LEASE_MS '{}' couldn't be interpreted as an unsigned integer value",
+                        value
+                    ))
+                })?);
+            }
+            if key == "STATE_WAIT" && !value.is_empty() {
+                args.state_wait |= value.parse::<bool>().map_err(|_| {
+                    CliError::config(format!(
+                        "STATE_WAIT '{}' couldn't be interpreted as 'true' or 'false'",
                         value
-                    )
-                }));
+                    ))
+                })?;
             }
             if key == "COOLDOWN_NS" && !value.is_empty() && args.cooldown_ns.is_none() {
-                args.cooldown_ns = Some(value.parse::<u64>().unwrap_or_else(|_| {
-                    panic!(
-                    "ERROR: COOLDOWN_NS '{}' couldn't be interpreted as an unsigned integer value",
-                    value
-                )
-                }));
+                args.cooldown_ns = Some(value.parse::<u64>().map_err(|_| {
+                    CliError::config(format!(
+                        "COOLDOWN_NS '{}' couldn't be interpreted as an unsigned integer value",
+                        value
+                    ))
+                })?);
             }
         }
     }
-    if args.quantity.is_some() && args.number.is_some() {
-        panic!(
-            "ERROR: Conflicting parameters. Must only specify one of either '--quantity,-q' or '--number,-n'"
-        )
+    if let Some(name) = args.preset.as_ref() {
+        let preset = config::SequenceConfig::preset(name).ok_or_else(|| {
+            CliError::config(format!(
+                "Unknown preset '{}'. Expected one of '{}'",
+                name,
+                config::SequenceConfig::PRESETS.join("', '")
+            ))
+        })?;
+        apply_preset(args, &preset, &mut configured_node_subfields);
     }
     if args.sign_bits.is_some() && args.unused_bits.is_some() {
-        panic!(
-            "ERROR: Conflicting parameters. Must only specify one of either '--unused-bits,-u' or '--sign-bits'"
-        )
+        return Err(CliError::usage("Conflicting parameters. Must only specify one of either '--unused-bits,-u' or '--sign-bits'"));
     }
     if let Some(value) = args.sign_bits {
         if value > 7 {
-            panic!(
-                "ERROR: SIGN_BITS '{}' is larger than the maximum value of 7.",
+            return Err(CliError::config(format!(
+                "SIGN_BITS '{}' is larger than the maximum value of 7.",
                 value
-            )
+            )));
         }
     };
     if args.sign_bits.is_some() {
@@ -172,38 +505,102 @@ fn main() {
     }
     if let Some(value) = args.unused_bits {
         if value > 7 {
-            panic!(
-                "ERROR: UNUSED_BITS '{}' is larger than the maximum value of 7.",
+            return Err(CliError::config(format!(
+                "UNUSED_BITS '{}' is larger than the maximum value of 7.",
                 value
-            )
+            )));
         }
     };
     if let Some(value) = args.sequence_bits {
         if value > 16 {
-            panic!(
-                "ERROR: SEQUENCE_BITS '{}' is larger than the maximum value of 16.",
+            return Err(CliError::config(format!(
+                "SEQUENCE_BITS '{}' is larger than the maximum value of 16.",
                 value
-            )
+            )));
         }
         if value == 0 {
-            panic!(
-                "ERROR: SEQUENCE_BITS '{}' must be larger or equal than 1.",
+            return Err(CliError::config(format!(
+                "SEQUENCE_BITS '{}' must be larger or equal than 1.",
                 value
-            )
+            )));
         }
     };
+    let mut node_subfields: Vec<(String, u8, Option<u16>)> = [
+        ("datacenter", args.datacenter_id_bits),
+        ("worker", args.worker_id_bits),
+        ("process", args.process_id_bits),
+    ]
+    .iter()
+    .filter_map(|(name, bits)| bits.map(|bits| (name.to_string(), bits, None)))
+    .collect();
+    if node_subfields.is_empty() {
+        node_subfields = configured_node_subfields
+            .iter()
+            .map(|subfield| {
+                let value = u16::try_from(subfield.value).map_err(|_| CliError::config(format!("Node ID subfield '{}' value '{}' is larger than the maximum value of 65535.",
+                        subfield.name, subfield.value)))?;
+                Ok((subfield.name.clone(), subfield.bits, Some(value)))
+            })
+            .collect::<Result<_, CliError>>()?;
+    }
+    for (name, value) in [
+        ("datacenter", args.datacenter_id),
+        ("worker", args.worker_id),
+        ("process", args.process_id),
+    ] {
+        if let Some(value) = value {
+            match node_subfields
+                .iter_mut()
+                .find(|(subfield, _, _)| subfield == name)
+            {
+                Some(subfield) => subfield.2 = Some(value),
+                None => {
+                    return Err(CliError::usage(format!(
+                        "Node ID subfield '{}' requires its number of bits '--{}-id-bits'",
+                        name, name
+                    )))
+                }
+            }
+        }
+    }
+    if !node_subfields.is_empty() {
+        if args.node_id.is_some()
+            && (args.datacenter_id.is_some()
+                || args.worker_id.is_some()
+                || args.process_id.is_some())
+        {
+            return Err(CliError::usage("Conflicting parameters. Must only specify one of either '--node-id,-i' or node ID subfields '--datacenter-id', '--worker-id', '--process-id'"));
+        }
+        // An explicit node ID replaces the subfield values of a preset or a
+        // configuration file
+        if args.node_id.is_some() {
+            for subfield in node_subfields.iter_mut() {
+                subfield.2 = None;
+            }
+        }
+        let subfield_bits: u8 = node_subfields.iter().map(|(_, bits, _)| bits).sum();
+        if let Some(value) = args.node_id_bits {
+            if value != subfield_bits {
+                return Err(CliError::config(format!(
+                    "NODE_ID_BITS '{}' does not match the sum of node ID subfield bits '{}'.",
+                    value, subfield_bits
+                )));
+            }
+        }
+        args.node_id_bits = Some(subfield_bits);
+    }
     if let Some(value) = args.node_id_bits {
         if value > 16 {
-            panic!(
-                "ERROR: NODE_ID_BITS '{}' is larger than the maximum value of 16.",
+            return Err(CliError::config(format!(
+                "NODE_ID_BITS '{}' is larger than the maximum value of 16.",
                 value
-            )
+            )));
         }
         if value == 0 {
-            panic!(
-                "ERROR: NODE_ID_BITS '{}' must be larger or equal than 1.",
+            return Err(CliError::config(format!(
+                "NODE_ID_BITS '{}' must be larger or equal than 1.",
                 value
-            )
+            )));
         }
     };
     if args.custom_epoch.is_none() {
@@ -222,6 +619,43 @@ fn main() {
         args.sequence_bits = Some(11_u8);
     }
 
+    if (args.node_id_lease_dir.is_some() || args.coordinator.is_some())
+        && (args.node_id_source.is_some()
+            || (args.node_id_lease_dir.is_some() && args.coordinator.is_some())
+            || args.node_id.is_some()
+            || args.datacenter_id.is_some()
+            || args.worker_id.is_some()
+            || args.process_id.is_some())
+    {
+        return Err(CliError::usage("Conflicting parameters. Must only specify one of either '--coordinator', '--node-id-lease-dir', '--node-id-source' or the node ID '--node-id,-i', '--datacenter-id', '--worker-id', '--process-id'"));
+    }
+    if let Some(value) = args.node_id_source.as_ref() {
+        if args.node_id.is_some()
+            || args.datacenter_id.is_some()
+            || args.worker_id.is_some()
+            || args.process_id.is_some()
+        {
+            return Err(CliError::usage("Conflicting parameters. Must only specify one of either '--node-id-source' or the node ID '--node-id,-i', '--datacenter-id', '--worker-id', '--process-id'"));
+        }
+        let source = value
+            .parse::<node_id::NodeIdSource>()
+            .map_err(CliError::config)?;
+        if !layout_only {
+            let derived = source
+                .derive(args.node_id_bits.unwrap())
+                .map_err(CliError::config)?;
+            eprintln!("{}", derived);
+            if let Some(warning) = derived.warning.as_ref() {
+                eprintln!("{}", warning);
+            }
+            args.node_id = Some(derived.node_id);
+        }
+        // The derived node ID replaces configured subfield values
+        for subfield in node_subfields.iter_mut() {
+            subfield.2 = None;
+        }
+    }
+
     if args.node_id.is_none() {
         args.node_id = Some(0_u16);
     }
@@ -234,39 +668,25 @@ fn main() {
         args.cooldown_ns = Some(1000_u64);
     }
 
-    if args.number.is_none() {
-        if let Some(value) = args.quantity {
-            args.number = Some(value)
-        } else {
-            args.number = Some(1_usize)
-        }
-    }
-    if let Some(value) = args.number {
-        if value == 0 {
-            println!("WARNING: No ids were requested. Exiting.");
-            process::exit(0x0100);
-        }
-    }
-    let custom_epoch_millis_i128 =
-        OffsetDateTime::parse(args.custom_epoch.as_ref().unwrap(), &Rfc3339)
-            .unwrap_or_else(|_| {
-                panic!(
-                    "ERROR: Could not parse CUSTOM_EPOCH '{}' as an RFC-3339/ISO-8601 datetime.",
-                    args.custom_epoch.as_ref().unwrap()
-                )
-            })
-            .unix_timestamp_nanos()
-            / 1000000;
-    let custom_epoch_millis = i64::try_from(custom_epoch_millis_i128).unwrap();
-    let custom_epoch = UNIX_EPOCH
-        .checked_add(Duration::from_millis(custom_epoch_millis as u64))
-        .unwrap_or_else(|| {
-            panic!(
-            "ERROR: Could not generate a SystemTime custom epoch from milliseconds timestamp '{}'",
-            custom_epoch_millis
-        )
-        });
-    let properties = Rc::new(sequence_generator::SequenceProperties::new(
+    let custom_epoch_millis = OffsetDateTime::parse(args.custom_epoch.as_ref().unwrap(), &Rfc3339)
+        .map_err(|_| {
+            CliError::config(format!(
+                "Could not parse CUSTOM_EPOCH '{}' as an RFC-3339/ISO-8601 datetime.",
+                args.custom_epoch.as_ref().unwrap()
+            ))
+        })?
+        .unix_timestamp_nanos()
+        / 1000000;
+    let custom_epoch = u64::try_from(custom_epoch_millis)
+        .ok()
+        .and_then(|millis| UNIX_EPOCH.checked_add(Duration::from_millis(millis)))
+        .ok_or_else(|| {
+            CliError::config(format!(
+                "CUSTOM_EPOCH '{}' must not be before the UNIX epoch 1970-01-01T00:00:00Z.",
+                args.custom_epoch.as_ref().unwrap()
+            ))
+        })?;
+    let mut properties = sequence_generator::SequenceProperties::try_new(
         custom_epoch,
         args.node_id_bits.unwrap(),
         args.node_id.unwrap(),
@@ -274,44 +694,256 @@ fn main() {
         args.micros_ten_power.unwrap(),
         args.unused_bits.unwrap(),
         args.cooldown_ns.unwrap(),
-    ));
-    let mut vector_ids: Vec<u64> = vec![0; args.number.unwrap()];
-    if args.debug {
-        let time_now = SystemTime::now();
-        for element in vector_ids.iter_mut() {
-            *element = sequence_generator::generate_id(&properties).unwrap_or_else(
-                |error| {
-                    panic!(
-                        "SequenceGeneratorError: Failed to get ID from properties {:?}. SystemTimeError difference {:?}",
-                        properties,
-                        (error).duration()
-                    )
-                }
-            );
-        }
-        let elapsed = time_now
-            .elapsed()
-            .expect("ERROR: Failed to get elapsed time.")
-            .as_nanos();
-        for (index, element) in vector_ids.into_iter().enumerate() {
-            println!("{}: {}", index, element);
+    )
+    .map_err(CliError::config)?;
+    if !node_subfields.is_empty() {
+        let subfields: Vec<(&str, u8)> = node_subfields
+            .iter()
+            .map(|(name, bits, _)| (name.as_str(), *bits))
+            .collect();
+        properties
+            .try_set_node_subfields(&subfields)
+            .map_err(CliError::config)?;
+        for (name, _, value) in node_subfields.iter() {
+            if let Some(value) = value {
+                properties
+                    .try_set_node_subfield(name, *value)
+                    .map_err(CliError::config)?;
+            }
         }
-        println!(
-            "It took {} nanoseconds, time per id: {:.2} ns",
-            elapsed,
-            elapsed as f64 / args.number.unwrap() as f64
+    }
+    #[cfg(not(unix))]
+    if args.node_id_lease_dir.is_some() && !layout_only {
+        return Err(CliError::usage(
+            "Node ID lease directories '--node-id-lease-dir' are only supported on Unix",
+        ));
+    }
+    #[cfg(unix)]
+    if let Some(directory) = args.node_id_lease_dir.as_ref().filter(|_| !layout_only) {
+        let allocator = node_id_lease::NodeIdAllocator::new(directory).map_err(|error| {
+            CliError::io(format!(
+                "Could not use node ID lease directory '{}': {}",
+                directory, error
+            ))
+        })?;
+        properties.lease_node_id(&allocator).map_err(|error| {
+            CliError::generator(
+                &format!("Could not lease a node ID in '{}'", directory),
+                error,
+            )
+        })?;
+        eprintln!(
+            "Node ID {} leased through '{}'",
+            properties.node_id,
+            properties.node_id_lease().unwrap().path().display()
+        );
+    }
+    if let Some(address) = args.coordinator.as_ref().filter(|_| !layout_only) {
+        let lease =
+            coordinator::CoordinatorLease::acquire(address.as_str(), properties.node_id_bits)
+                .map_err(|error| {
+                    CliError::io(format!(
+                        "Could not lease a node ID from coordinator '{}': {}",
+                        address, error
+                    ))
+                })?;
+        eprintln!(
+            "Node ID {} leased from coordinator '{}'",
+            lease.node_id(),
+            address
         );
-    } else {
-        for (index, element) in vector_ids.iter_mut().enumerate() {
-            *element = sequence_generator::generate_id(&properties).unwrap_or_else(
-                |error| {
-                    panic!(
-                        "SequenceGeneratorError: Failed to get ID from properties {:?}. SystemTimeError difference {:?}",
-                        properties,
-                        (error).duration()
-                    )
-                });
-            println!("{}: {}", index, element);
+        properties
+            .try_set_coordinator_lease(lease)
+            .map_err(CliError::config)?;
+    }
+    for spec in args.extra_field.iter() {
+        let (name, bits, value) = parse_extra_field(spec)?;
+        properties
+            .try_add_field(&name, bits)
+            .map_err(CliError::config)?;
+        if let Some(value) = value {
+            properties
+                .try_set_field_value(&name, value)
+                .map_err(CliError::config)?;
         }
     }
+    if let Some(value) = args.checksum.as_ref() {
+        let algorithm = value
+            .parse::<checksum::ChecksumAlgorithm>()
+            .map_err(CliError::config)?;
+        properties
+            .try_set_checksum(algorithm, args.checksum_bits.unwrap_or(4))
+            .map_err(CliError::config)?;
+    }
+    match (args.layout_version, args.layout_version_bits) {
+        (Some(version), Some(bits)) => properties
+            .try_set_layout_version(version, bits)
+            .map_err(CliError::config)?,
+        (None, None) => {}
+        _ => {
+            return Err(CliError::usage(
+                "Layout version requires both '--layout-version' and '--layout-version-bits'",
+            ))
+        }
+    }
+    if let Some(value) = args.field_order.as_ref() {
+        let field_order =
+            layout::parse_field_order(value, properties.layout()).map_err(CliError::config)?;
+        let field_order: Vec<&str> = field_order.iter().map(String::as_str).collect();
+        properties
+            .try_set_field_order(&field_order)
+            .map_err(CliError::config)?;
+    }
+    if let Some(state_file) = args.state_file.as_ref().filter(|_| !layout_only) {
+        let policy = if args.state_wait {
+            high_water_mark::StartupPolicy::Wait
+        } else {
+            high_water_mark::StartupPolicy::Refuse
+        };
+        properties
+            .set_high_water_mark(high_water_mark::HighWaterMark::new(
+                state_file,
+                Duration::from_millis(args.state_lease_ms.unwrap_or(0)),
+                policy,
+            ))
+            .map_err(|error| CliError::generator(&format!("State file '{}'", state_file), error))?;
+    }
+    Ok(properties)
+}
+
+/// Parse an extra field given as 'name:bits' or 'name:bits=value'.
+fn parse_extra_field(spec: &str) -> Result<(String, u8, Option<u64>), CliError> {
+    let (definition, value) = match spec.split_once('=') {
+        Some((definition, value)) => (definition, Some(value)),
+        None => (spec, None),
+    };
+    let (name, bits) = definition.split_once(':').ok_or_else(|| {
+        CliError::config(format!(
+            "Extra field '{}' must be given as 'name:bits' or 'name:bits=value'",
+            spec
+        ))
+    })?;
+    let bits = bits.trim().parse::<u8>().map_err(|_| {
+        CliError::config(format!(
+            "Bits of extra field '{}' couldn't be interpreted as value between 1 and 63",
+            spec
+        ))
+    })?;
+    let value = value
+        .map(|value| {
+            value.trim().parse::<u64>().map_err(|_| CliError::config(format!("Value of extra field '{}' couldn't be interpreted as an unsigned integer value",
+                spec)))
+        })
+        .transpose()?;
+    Ok((name.trim().to_owned(), bits, value))
+}
+
+/// Fill the parameters still unassigned with the values of a preset layout.
+fn apply_preset(
+    args: &mut Opt,
+    preset: &config::SequenceConfig,
+    node_subfields: &mut Vec<config::FieldConfig>,
+) {
+    if args.custom_epoch.is_none() {
+        args.custom_epoch = Some(
+            OffsetDateTime::from(preset.custom_epoch)
+                .format(&Rfc3339)
+                .expect("ERROR: Failed to format the custom epoch of the preset."),
+        );
+    }
+    if args.node_id_bits.is_none()
+        && args.datacenter_id_bits.is_none()
+        && args.worker_id_bits.is_none()
+        && args.process_id_bits.is_none()
+        && node_subfields.is_empty()
+    {
+        args.node_id_bits = Some(preset.node_id_bits);
+        *node_subfields = preset.node_subfields.clone();
+    }
+    if args.sequence_bits.is_none() {
+        args.sequence_bits = Some(preset.sequence_bits);
+    }
+    if args.micros_ten_power.is_none() {
+        args.micros_ten_power = Some(preset.micros_ten_power);
+    }
+    if args.unused_bits.is_none() && args.sign_bits.is_none() {
+        args.unused_bits = Some(preset.unused_bits);
+    }
+    if args.cooldown_ns.is_none() {
+        args.cooldown_ns = Some(preset.backoff_cooldown_start_ns);
+    }
+    if args.field_order.is_none() {
+        args.field_order = preset.field_order.as_ref().map(|names| names.join(","));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn node_id_overrides_subfield_values() {
+        use super::*;
+        use std::fs;
+        let build = |args: &[&str]| {
+            let mut args = Opt::parse_from(
+                ["sequence_generator", "--dotenv-file", "missing.env"]
+                    .iter()
+                    .chain(args),
+            );
+            build_properties(&mut args, false).unwrap()
+        };
+        let discord = build(&["--preset", "discord", "--node-id", "5"]);
+        assert_eq!(discord.node_id, 5);
+        assert_eq!(discord.node_id_layout().unwrap().get(5, "process"), Some(5));
+        assert_eq!(build(&["--preset", "discord"]).node_id, 0);
+        let config_file =
+            env::temp_dir().join(format!("sequence-generator-node-id-{}.toml", process::id()));
+        fs::write(
+            &config_file,
+            "node_id = 33\nnode_subfields = [{ name = \"datacenter\", bits = 4, value = 0 }, { name = \"worker\", bits = 5, value = 0 }]\n",
+        )
+        .unwrap();
+        let configured = build(&["--config", config_file.to_str().unwrap()]);
+        fs::remove_file(&config_file).unwrap();
+        assert_eq!(configured.node_id, 33);
+        assert_eq!(
+            configured.node_id_layout().unwrap().split(33),
+            vec![("datacenter".to_owned(), 1), ("worker".to_owned(), 1)]
+        );
+    }
+
+    #[test]
+    fn layout_only_skips_node_id_allocation() {
+        use super::*;
+        let parse = |args: &[&str]| {
+            Opt::parse_from(
+                ["sequence_generator", "--dotenv-file", "missing.env"]
+                    .iter()
+                    .chain(args),
+            )
+        };
+        let coordinator = ["--coordinator", "127.0.0.1:1"];
+        assert!(build_properties(&mut parse(&coordinator), false).is_err());
+        let layout = build_properties(&mut parse(&coordinator), true).unwrap();
+        assert!(layout.coordinator_lease().is_none());
+        let source = ["--node-id-source", "env:SEQUENCE_GENERATOR_MISSING_NODE_ID"];
+        assert!(build_properties(&mut parse(&source), false).is_err());
+        assert_eq!(
+            build_properties(&mut parse(&source), true).unwrap().node_id,
+            0
+        );
+    }
+
+    #[test]
+    fn custom_epoch_before_unix_epoch() {
+        use super::*;
+        let mut args = Opt::parse_from([
+            "sequence_generator",
+            "--dotenv-file",
+            "missing.env",
+            "--custom-epoch",
+            "1969-12-31T00:00:00Z",
+        ]);
+        let error = build_properties(&mut args, false).unwrap_err();
+        assert_eq!(error.exit_code(), 3);
+    }
 }
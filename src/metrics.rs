@@ -0,0 +1,194 @@
+//! Counters and histograms of a generator, attached with
+//! `SequenceProperties::set_metrics` and rendered in the Prometheus text
+//! exposition format.
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds in microseconds of the buckets of the exhaustion wait
+/// histogram, from 10 µs to 1 s.
+const EXHAUSTION_WAIT_BUCKETS: [u64; 6] = [10, 100, 1_000, 10_000, 100_000, 1_000_000];
+/// Upper bounds in microseconds of the buckets of the clock regression
+/// histogram, from 1 ms to 10 minutes.
+const CLOCK_REGRESSION_BUCKETS: [u64; 7] = [
+    1_000,
+    10_000,
+    100_000,
+    1_000_000,
+    10_000_000,
+    60_000_000,
+    600_000_000,
+];
+
+/// Metrics shared by a generator and whoever exposes them, updated with
+/// atomic operations only.
+#[derive(Debug)]
+pub struct GeneratorMetrics {
+    ids_issued: AtomicU64,
+    exhaustion_waits: Histogram,
+    clock_regressions: Histogram,
+    /// `u64::MAX` until the first ID is issued
+    timestamp_headroom_micros: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Histogram {
+    bounds_micros: &'static [u64],
+    counts: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl GeneratorMetrics {
+    pub fn new() -> Self {
+        GeneratorMetrics {
+            ids_issued: AtomicU64::new(0),
+            exhaustion_waits: Histogram::new(&EXHAUSTION_WAIT_BUCKETS),
+            clock_regressions: Histogram::new(&CLOCK_REGRESSION_BUCKETS),
+            timestamp_headroom_micros: AtomicU64::new(u64::MAX),
+        }
+    }
+    pub fn ids_issued(&self) -> u64 {
+        self.ids_issued.load(Ordering::Relaxed)
+    }
+    /// Times the sequence was exhausted and generation waited for the next
+    /// tick.
+    pub fn exhaustion_waits(&self) -> u64 {
+        self.exhaustion_waits.count.load(Ordering::Relaxed)
+    }
+    /// Times the system clock was found behind the last used timestamp.
+    pub fn clock_regressions(&self) -> u64 {
+        self.clock_regressions.count.load(Ordering::Relaxed)
+    }
+    /// Time left until the timestamp field overflows, as of the last ID
+    /// issued.
+    pub fn timestamp_headroom(&self) -> Option<Duration> {
+        match self.timestamp_headroom_micros.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+    pub(crate) fn record_id(&self, timestamp_headroom: Duration) {
+        self.ids_issued.fetch_add(1, Ordering::Relaxed);
+        self.timestamp_headroom_micros
+            .store(timestamp_headroom.as_micros() as u64, Ordering::Relaxed);
+    }
+    pub(crate) fn record_exhaustion_wait(&self, waited: Duration) {
+        self.exhaustion_waits.observe(waited);
+    }
+    pub(crate) fn record_clock_regression(&self, behind: Duration) {
+        self.clock_regressions.observe(behind);
+    }
+    /// Every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(
+            text,
+            "# HELP sequence_generator_ids_issued_total IDs generated.\n\
+             # TYPE sequence_generator_ids_issued_total counter\n\
+             sequence_generator_ids_issued_total {}",
+            self.ids_issued()
+        );
+        self.exhaustion_waits.render(
+            &mut text,
+            "sequence_generator_exhaustion_wait_seconds",
+            "Waits for the next tick after the sequence was exhausted.",
+        );
+        self.clock_regressions.render(
+            &mut text,
+            "sequence_generator_clock_regression_seconds",
+            "Distance the system clock was found behind the last used timestamp.",
+        );
+        if let Some(headroom) = self.timestamp_headroom() {
+            let _ = writeln!(
+                text,
+                "# HELP sequence_generator_timestamp_headroom_seconds Time left until the timestamp field overflows.\n\
+                 # TYPE sequence_generator_timestamp_headroom_seconds gauge\n\
+                 sequence_generator_timestamp_headroom_seconds {}",
+                headroom.as_secs()
+            );
+        }
+        text
+    }
+}
+
+impl Default for GeneratorMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Histogram {
+    fn new(bounds_micros: &'static [u64]) -> Self {
+        Histogram {
+            bounds_micros,
+            counts: bounds_micros.iter().map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+    fn observe(&self, value: Duration) {
+        let micros = value.as_micros() as u64;
+        if let Some(bucket) = self.bounds_micros.iter().position(|bound| micros <= *bound) {
+            self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+    /// Cumulative buckets, as Prometheus expects them.
+    fn render(&self, text: &mut String, name: &str, help: &str) {
+        let _ = writeln!(text, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds_micros.iter().zip(self.counts.iter()) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(
+                text,
+                "{}_bucket{{le=\"{}\"}} {}",
+                name,
+                *bound as f64 / 1e6,
+                cumulative
+            );
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(text, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(
+            text,
+            "{}_sum {}\n{}_count {}",
+            name,
+            self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6,
+            name,
+            count
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn record_and_render() {
+        use super::*;
+        let metrics = GeneratorMetrics::new();
+        metrics.record_id(Duration::from_secs(3600));
+        metrics.record_id(Duration::from_secs(3599));
+        metrics.record_exhaustion_wait(Duration::from_micros(50));
+        metrics.record_clock_regression(Duration::from_millis(5));
+        metrics.record_clock_regression(Duration::from_secs(3600));
+        let text = metrics.render();
+        assert!(text.contains("sequence_generator_ids_issued_total 2\n"));
+        assert!(
+            text.contains("sequence_generator_exhaustion_wait_seconds_bucket{le=\"0.00001\"} 0\n")
+        );
+        assert!(
+            text.contains("sequence_generator_exhaustion_wait_seconds_bucket{le=\"0.0001\"} 1\n")
+        );
+        assert!(
+            text.contains("sequence_generator_clock_regression_seconds_bucket{le=\"0.01\"} 1\n")
+        );
+        assert!(text.contains("sequence_generator_clock_regression_seconds_bucket{le=\"600\"} 1\n"));
+        assert!(
+            text.contains("sequence_generator_clock_regression_seconds_bucket{le=\"+Inf\"} 2\n")
+        );
+        assert!(text.contains("sequence_generator_clock_regression_seconds_sum 3600.005\n"));
+        assert!(text.contains("sequence_generator_timestamp_headroom_seconds 3599\n"));
+    }
+}
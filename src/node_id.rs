@@ -0,0 +1,465 @@
+use std::convert::TryFrom;
+use std::env;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Where the node ID is derived from when it is not set explicitly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeIdSource {
+    /// Trailing number of the hostname, e.g. 17 for `worker-17`, such as the
+    /// ordinal of a Kubernetes StatefulSet pod.
+    HostnameOrdinal,
+    /// Value of the named environment variable.
+    Env(String),
+    /// Hash of the hostname truncated to `node_id_bits`. Unlike the other
+    /// sources, different hosts may get the same node ID.
+    HostnameHash,
+    /// Host part of the IPv4 address of the named interface, or of the first
+    /// non-loopback interface preferring private addresses, like Sonyflake.
+    /// The host part within the network of the address must fit in
+    /// `node_id_bits`.
+    Ipv4(Option<String>),
+    /// Host part of the IPv6 address of the named interface, or of the first
+    /// non-loopback interface preferring non link-local addresses.
+    Ipv6(Option<String>),
+    /// Lower `node_id_bits` of the MAC address of the named interface, or of
+    /// the first non-loopback interface. Hosts whose MAC addresses share the
+    /// lower bits get the same node ID.
+    Mac(Option<String>),
+}
+
+/// Node ID derived from a `NodeIdSource`, with a description of where it
+/// came from to report at startup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivedNodeId {
+    pub node_id: u16,
+    pub description: String,
+    /// Collision risk of a node ID hashed or taken from the lower bits of
+    /// an address.
+    pub warning: Option<String>,
+}
+
+/// Host information node IDs are derived from. `HostInfo::current` reads
+/// it from the system, tests can fill it by hand.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostInfo {
+    pub hostname: Option<String>,
+    pub interfaces: Vec<NetworkInterface>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetworkInterface {
+    pub name: String,
+    /// Addresses with the prefix length of their network, e.g. 24 for
+    /// 10.0.1.5/24.
+    pub addresses: Vec<(IpAddr, u8)>,
+    pub mac: Option<[u8; 6]>,
+}
+
+impl HostInfo {
+    pub fn current() -> Self {
+        let mut interfaces: Vec<NetworkInterface> = Vec::new();
+        for interface in if_addrs::get_if_addrs().unwrap_or_default() {
+            let (address, netmask) = match &interface.addr {
+                if_addrs::IfAddr::V4(address) => (
+                    IpAddr::V4(address.ip),
+                    u32::from(address.netmask).count_ones(),
+                ),
+                if_addrs::IfAddr::V6(address) => (
+                    IpAddr::V6(address.ip),
+                    u128::from(address.netmask).count_ones(),
+                ),
+            };
+            match interfaces
+                .iter_mut()
+                .find(|known| known.name == interface.name)
+            {
+                Some(known) => known.addresses.push((address, netmask as u8)),
+                None => interfaces.push(NetworkInterface {
+                    mac: interface_mac(&interface.name),
+                    name: interface.name,
+                    addresses: vec![(address, netmask as u8)],
+                }),
+            }
+        }
+        HostInfo {
+            hostname: hostname::get()
+                .ok()
+                .and_then(|hostname| hostname.into_string().ok()),
+            interfaces,
+        }
+    }
+}
+
+impl NodeIdSource {
+    /// Derive the node ID from the current host and environment.
+    pub fn derive(&self, node_id_bits: u8) -> Result<DerivedNodeId, String> {
+        self.derive_from(&HostInfo::current(), node_id_bits)
+    }
+    /// Derive the node ID from `host`, checking that it fits in
+    /// `node_id_bits`.
+    pub fn derive_from(&self, host: &HostInfo, node_id_bits: u8) -> Result<DerivedNodeId, String> {
+        let max_node_id = (1_u64 << node_id_bits) - 1;
+        let hostname = || {
+            host.hostname
+                .as_deref()
+                .ok_or_else(|| "ERROR: Could not retrieve the hostname".to_owned())
+        };
+        let (value, description, warning) = match self {
+            NodeIdSource::Ipv4(interface) | NodeIdSource::Ipv6(interface) => {
+                let ipv4 = matches!(self, NodeIdSource::Ipv4(_));
+                let (name, address, prefix_len) = select_address(host, interface.as_deref(), ipv4)?;
+                let description =
+                    format!("address {}/{} of interface '{}'", address, prefix_len, name);
+                let (address, address_bits) = match address {
+                    IpAddr::V4(address) => (u32::from(address) as u128, 32),
+                    IpAddr::V6(address) => (u128::from(address), 128),
+                };
+                if prefix_len >= address_bits {
+                    // Single host networks, as on many cloud and container
+                    // interfaces, have no host part
+                    (
+                        (address & max_node_id as u128) as u64,
+                        description,
+                        Some(format!(
+                            "WARNING: Prefix /{} has no host part, node ID taken from the lower {} bits of the address, hosts sharing them will collide.",
+                            prefix_len, node_id_bits
+                        )),
+                    )
+                } else {
+                    let host_part = address & (u128::MAX >> (128 - address_bits + prefix_len));
+                    if host_part > max_node_id as u128 {
+                        return Err(format!(
+                            "ERROR: Host part '{}' of {} is larger than the maximum value of {} for {} node ID bits",
+                            host_part, description, max_node_id, node_id_bits
+                        ));
+                    }
+                    (host_part as u64, description, None)
+                }
+            }
+            NodeIdSource::Mac(interface) => {
+                let (name, mac) = host
+                    .interfaces
+                    .iter()
+                    .filter(|candidate| match interface {
+                        Some(name) => candidate.name == *name,
+                        None => !is_loopback(candidate),
+                    })
+                    .find_map(|candidate| {
+                        candidate
+                            .mac
+                            .filter(|mac| *mac != [0; 6])
+                            .map(|mac| (&candidate.name, mac))
+                    })
+                    .ok_or_else(|| match interface {
+                        Some(name) => format!("ERROR: Interface '{}' has no MAC address", name),
+                        None => "ERROR: No network interface with a MAC address".to_owned(),
+                    })?;
+                let mut bytes = [0_u8; 8];
+                bytes[2..].copy_from_slice(&mac);
+                (
+                    u64::from_be_bytes(bytes) & max_node_id,
+                    format!(
+                        "MAC address {} of interface '{}'",
+                        mac.iter()
+                            .map(|byte| format!("{:02x}", byte))
+                            .collect::<Vec<String>>()
+                            .join(":"),
+                        name
+                    ),
+                    Some(format!(
+                        "WARNING: Node ID taken from the lower {} bits of the MAC address, hosts sharing them will collide.",
+                        node_id_bits
+                    )),
+                )
+            }
+            NodeIdSource::HostnameOrdinal => {
+                let hostname = hostname()?;
+                let ordinal = hostname_ordinal(hostname).ok_or_else(|| {
+                    format!(
+                        "ERROR: Hostname '{}' does not end with a number, as in 'worker-17'",
+                        hostname
+                    )
+                })?;
+                (ordinal, format!("ordinal of hostname '{}'", hostname), None)
+            }
+            NodeIdSource::Env(name) => {
+                let value = env::var(name)
+                    .map_err(|_| format!("ERROR: Environment variable '{}' is not set", name))?;
+                let node_id = value.trim().parse::<u64>().map_err(|_| {
+                    format!(
+                        "ERROR: Environment variable {} '{}' couldn't be interpreted as an unsigned integer value",
+                        name, value
+                    )
+                })?;
+                (node_id, format!("environment variable '{}'", name), None)
+            }
+            NodeIdSource::HostnameHash => {
+                let hostname = hostname()?;
+                (
+                    fnv1a(hostname.as_bytes()) & max_node_id,
+                    format!("hash of hostname '{}'", hostname),
+                    Some(format!(
+                        "WARNING: Node ID hashed into {} bits ({} values), different hosts may collide. Prefer an ordinal or an explicit node ID.",
+                        node_id_bits,
+                        max_node_id + 1
+                    )),
+                )
+            }
+        };
+        if value > max_node_id {
+            return Err(format!(
+                "ERROR: Node ID '{}' from {} is larger than the maximum value of {} for {} node ID bits",
+                value, description, max_node_id, node_id_bits
+            ));
+        }
+        Ok(DerivedNodeId {
+            node_id: value as u16,
+            description,
+            warning,
+        })
+    }
+}
+
+impl fmt::Display for NodeIdSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeIdSource::HostnameOrdinal => f.write_str("hostname"),
+            NodeIdSource::Env(name) => write!(f, "env:{}", name),
+            NodeIdSource::HostnameHash => f.write_str("hostname-hash"),
+            NodeIdSource::Ipv4(interface) => write_interface(f, "ipv4", interface),
+            NodeIdSource::Ipv6(interface) => write_interface(f, "ipv6", interface),
+            NodeIdSource::Mac(interface) => write_interface(f, "mac", interface),
+        }
+    }
+}
+
+impl FromStr for NodeIdSource {
+    type Err = String;
+
+    /// `hostname`, `hostname-hash`, `env:NAME`, or `ipv4`, `ipv6`, `mac`
+    /// optionally followed by the interface, e.g. `ipv4:eth0`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let (kind, argument) = match value.split_once(':') {
+            Some((kind, argument)) if !argument.trim().is_empty() => {
+                (kind, Some(argument.trim().to_owned()))
+            }
+            _ => (value, None),
+        };
+        match (kind.to_ascii_lowercase().as_str(), argument) {
+            ("hostname" | "hostname-ordinal" | "ordinal", None) => {
+                Ok(NodeIdSource::HostnameOrdinal)
+            }
+            ("hostname-hash" | "hash", None) => Ok(NodeIdSource::HostnameHash),
+            ("env", Some(name)) => Ok(NodeIdSource::Env(name)),
+            ("ipv4", interface) => Ok(NodeIdSource::Ipv4(interface)),
+            ("ipv6", interface) => Ok(NodeIdSource::Ipv6(interface)),
+            ("mac", interface) => Ok(NodeIdSource::Mac(interface)),
+            _ => Err(format!(
+                "ERROR: Unknown node ID source '{}'. Expected one of 'hostname', 'hostname-hash', 'env:NAME', 'ipv4[:INTERFACE]', 'ipv6[:INTERFACE]' or 'mac[:INTERFACE]'",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DerivedNodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Node ID {} from {}", self.node_id, self.description)
+    }
+}
+
+fn write_interface(
+    f: &mut fmt::Formatter<'_>,
+    kind: &str,
+    interface: &Option<String>,
+) -> fmt::Result {
+    match interface {
+        Some(interface) => write!(f, "{}:{}", kind, interface),
+        None => f.write_str(kind),
+    }
+}
+
+fn is_loopback(interface: &NetworkInterface) -> bool {
+    interface.name == "lo"
+        || (!interface.addresses.is_empty()
+            && interface
+                .addresses
+                .iter()
+                .all(|(address, _)| address.is_loopback()))
+}
+
+/// Address of the named interface, or the preferred address of the first
+/// non-loopback interface: private IPv4 and non link-local IPv6 addresses.
+fn select_address<'a>(
+    host: &'a HostInfo,
+    interface: Option<&str>,
+    ipv4: bool,
+) -> Result<(&'a str, IpAddr, u8), String> {
+    let candidates: Vec<(&str, IpAddr, u8)> = host
+        .interfaces
+        .iter()
+        .filter(|candidate| match interface {
+            Some(name) => candidate.name == name,
+            None => !is_loopback(candidate),
+        })
+        .flat_map(|candidate| {
+            candidate
+                .addresses
+                .iter()
+                .map(move |(address, prefix_len)| (candidate.name.as_str(), *address, *prefix_len))
+        })
+        .filter(|(_, address, _)| {
+            address.is_ipv4() == ipv4 && (interface.is_some() || !address.is_loopback())
+        })
+        .collect();
+    let preferred = candidates.iter().find(|(_, address, _)| match address {
+        IpAddr::V4(address) => address.is_private(),
+        IpAddr::V6(address) => address.segments()[0] & 0xffc0 != 0xfe80,
+    });
+    preferred
+        .or_else(|| candidates.first())
+        .copied()
+        .ok_or_else(|| {
+            let family = if ipv4 { "IPv4" } else { "IPv6" };
+            match interface {
+                Some(name) => format!("ERROR: Interface '{}' has no {} address", name, family),
+                None => format!("ERROR: No network interface with an {} address", family),
+            }
+        })
+}
+
+/// MAC address of the interface, only available on Linux.
+fn interface_mac(name: &str) -> Option<[u8; 6]> {
+    let contents = std::fs::read_to_string(format!("/sys/class/net/{}/address", name)).ok()?;
+    let bytes: Vec<u8> = contents
+        .trim()
+        .split(':')
+        .map(|byte| u8::from_str_radix(byte, 16))
+        .collect::<Result<_, _>>()
+        .ok()?;
+    <[u8; 6]>::try_from(bytes.as_slice()).ok()
+}
+
+/// Trailing decimal number of `hostname`, ignoring any domain.
+fn hostname_ordinal(hostname: &str) -> Option<u64> {
+    let host = hostname.split('.').next().unwrap_or(hostname);
+    let digits = host.len() - host.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    host[host.len() - digits..].parse::<u64>().ok()
+}
+
+/// 64-bit FNV-1a, stable across platforms and releases unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn derive_node_id() {
+        use super::*;
+        let host = HostInfo {
+            hostname: Some("worker-17.ids.default.svc".to_owned()),
+            ..HostInfo::default()
+        };
+        let derived = NodeIdSource::HostnameOrdinal.derive_from(&host, 9).unwrap();
+        assert_eq!(derived.node_id, 17);
+        assert!(derived.warning.is_none());
+        assert!(NodeIdSource::HostnameOrdinal.derive_from(&host, 4).is_err());
+        let unnumbered = HostInfo {
+            hostname: Some("worker".to_owned()),
+            ..HostInfo::default()
+        };
+        assert!(NodeIdSource::HostnameOrdinal
+            .derive_from(&unnumbered, 9)
+            .is_err());
+        let hashed = NodeIdSource::HostnameHash.derive_from(&host, 9).unwrap();
+        assert!(hashed.node_id < 512);
+        assert!(hashed.warning.is_some());
+        assert_eq!(
+            hashed,
+            NodeIdSource::HostnameHash.derive_from(&host, 9).unwrap()
+        );
+        assert!(NodeIdSource::HostnameHash
+            .derive_from(&HostInfo::default(), 9)
+            .is_err());
+        assert_eq!(
+            "env:POD_ORDINAL".parse::<NodeIdSource>(),
+            Ok(NodeIdSource::Env("POD_ORDINAL".to_owned()))
+        );
+        assert!(
+            NodeIdSource::Env("SEQUENCE_GENERATOR_TEST_UNSET".to_owned())
+                .derive_from(&host, 9)
+                .is_err()
+        );
+        assert!("serial".parse::<NodeIdSource>().is_err());
+    }
+
+    #[test]
+    fn derive_node_id_from_interfaces() {
+        use super::*;
+        let host = HostInfo {
+            hostname: None,
+            interfaces: vec![
+                NetworkInterface {
+                    name: "lo".to_owned(),
+                    addresses: vec![("127.0.0.1".parse().unwrap(), 8)],
+                    mac: Some([0; 6]),
+                },
+                NetworkInterface {
+                    name: "eth0".to_owned(),
+                    addresses: vec![
+                        ("203.0.113.9".parse().unwrap(), 24),
+                        ("10.1.2.3".parse().unwrap(), 16),
+                        ("fe80::1".parse().unwrap(), 64),
+                        ("2001:db8::1:42".parse().unwrap(), 112),
+                    ],
+                    mac: Some([0x02, 0x42, 0xac, 0x11, 0x00, 0x07]),
+                },
+                NetworkInterface {
+                    name: "eth1".to_owned(),
+                    addresses: vec![("192.168.7.200".parse().unwrap(), 24)],
+                    mac: None,
+                },
+                NetworkInterface {
+                    name: "eth2".to_owned(),
+                    addresses: vec![
+                        ("172.16.5.77".parse().unwrap(), 32),
+                        ("2001:db8::f21c".parse().unwrap(), 128),
+                    ],
+                    mac: None,
+                },
+            ],
+        };
+        let derive = |source: &str, node_id_bits| {
+            source
+                .parse::<NodeIdSource>()
+                .unwrap()
+                .derive_from(&host, node_id_bits)
+        };
+        // Private address preferred, 2.3 is the host part within 10.1.0.0/16
+        assert_eq!(derive("ipv4", 16).unwrap().node_id, 0x0203);
+        assert!(derive("ipv4", 9).is_err());
+        assert_eq!(derive("ipv4:eth1", 8).unwrap().node_id, 200);
+        assert_eq!(derive("ipv6", 16).unwrap().node_id, 0x42);
+        assert!(derive("ipv6:eth1", 16).is_err());
+        // Single host networks use the lower bits of the address
+        let single_host = derive("ipv4:eth2", 8).unwrap();
+        assert_eq!(single_host.node_id, 77);
+        assert!(single_host.warning.is_some());
+        assert_eq!(derive("ipv6:eth2", 12).unwrap().node_id, 0x21c);
+        let mac = derive("mac", 12).unwrap();
+        assert_eq!(mac.node_id, 0x007);
+        assert!(mac.warning.is_some());
+        assert!(derive("mac:eth1", 12).is_err());
+        assert!(derive("ipv4:wlan0", 16).is_err());
+        assert_eq!(
+            "ipv4:eth0".parse::<NodeIdSource>().unwrap().to_string(),
+            "ipv4:eth0"
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+/// Empty lease files younger than this may belong to a process that has not
+/// written its owner yet.
+const EMPTY_LEASE_GRACE: Duration = Duration::from_secs(10);
+
+/// Hands out the lowest node ID not leased by another process, through one
+/// lease file per node ID in a directory shared by the processes of a host,
+/// or by several hosts on a network file system.
+///
+/// Lease files are created exclusively, locked while held and contain the
+/// hostname and PID of their owner. Leases left behind by dead processes of
+/// the same host are taken over. Those of other hosts are never considered
+/// stale and must be removed by hand. Only available on Unix, where lease
+/// files are locked with `flock`.
+#[derive(Debug, Clone)]
+pub struct NodeIdAllocator {
+    directory: PathBuf,
+    hostname: String,
+}
+
+/// Node ID leased by a `NodeIdAllocator`, released when dropped.
+#[derive(Debug)]
+pub struct NodeIdLease {
+    node_id: u16,
+    path: PathBuf,
+    // Keeps the lock on the lease file
+    _file: File,
+}
+
+impl NodeIdAllocator {
+    /// Allocator over `directory`, created if missing.
+    pub fn new(directory: impl Into<PathBuf>) -> io::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        let hostname = hostname::get()?.to_string_lossy().into_owned();
+        Ok(NodeIdAllocator {
+            directory,
+            hostname,
+        })
+    }
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+    /// Lease the lowest free node ID that fits in `node_id_bits`.
+    pub fn acquire(&self, node_id_bits: u8) -> io::Result<NodeIdLease> {
+        let owner = format!("{} {}\n", self.hostname, process::id());
+        let max_node_id = (1_u32 << node_id_bits) - 1;
+        for node_id in 0..=max_node_id {
+            let path = self.directory.join(format!("node-{}.lease", node_id));
+            let file = match OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                // Locked in between by another process checking whether the
+                // new, still empty lease file is stale
+                Ok(file) if !try_lock(&file)? => {
+                    drop(file);
+                    fs::remove_file(&path)?;
+                    None
+                }
+                Ok(file) => Some(file),
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+                    self.take_over_stale(&path)?
+                }
+                Err(error) => return Err(error),
+            };
+            if let Some(mut file) = file {
+                file.write_all(owner.as_bytes())?;
+                file.sync_all()?;
+                return Ok(NodeIdLease {
+                    node_id: node_id as u16,
+                    path,
+                    _file: file,
+                });
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "all {} node IDs in '{}' are leased",
+                max_node_id + 1,
+                self.directory.display()
+            ),
+        ))
+    }
+    /// Lock and truncate the lease file at `path` if its owner is a dead
+    /// process of this host.
+    fn take_over_stale(&self, path: &Path) -> io::Result<Option<File>> {
+        let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        // Held by a live process, or removed and created again since opened
+        if !try_lock(&file)? || !is_same_file(&file, path)? {
+            return Ok(None);
+        }
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        let is_stale = match contents.split_whitespace().collect::<Vec<&str>>()[..] {
+            [] => file.metadata()?.modified()?.elapsed().unwrap_or_default() > EMPTY_LEASE_GRACE,
+            [hostname, pid] => {
+                hostname == self.hostname
+                    && pid
+                        .parse::<u32>()
+                        .map_or(false, |pid| !is_process_alive(pid))
+            }
+            _ => false,
+        };
+        if !is_stale {
+            return Ok(None);
+        }
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(Some(file))
+    }
+}
+
+impl NodeIdLease {
+    pub fn node_id(&self) -> u16 {
+        self.node_id
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for NodeIdLease {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Exclusive lock released when the file is closed, including when the
+/// process dies. `false` if another open file holds it.
+fn try_lock(file: &File) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(false),
+        _ => Err(error),
+    }
+}
+
+fn is_same_file(file: &File, path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let opened = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(current) => Ok(opened.dev() == current.dev() && opened.ino() == current.ino()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+fn is_process_alive(pid: u32) -> bool {
+    use std::convert::TryFrom;
+    let pid = match libc::pid_t::try_from(pid) {
+        Ok(pid) => pid,
+        Err(_) => return false,
+    };
+    let signaled = unsafe { libc::kill(pid, 0) } == 0;
+    signaled || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn lease_lowest_free() {
+        use super::*;
+        let directory = std::env::temp_dir().join(format!("node-id-leases-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let allocator = NodeIdAllocator::new(&directory).unwrap();
+        let first = allocator.acquire(2).unwrap();
+        let second = allocator.acquire(2).unwrap();
+        assert_eq!((first.node_id(), second.node_id()), (0, 1));
+        drop(first);
+        assert_eq!(allocator.acquire(2).unwrap().node_id(), 0);
+        // Lease of another host, never stale
+        fs::write(directory.join("node-0.lease"), "elsewhere 1\n").unwrap();
+        // Lease of a dead process of this host
+        fs::write(
+            directory.join("node-2.lease"),
+            format!("{} {}\n", allocator.hostname, u32::MAX),
+        )
+        .unwrap();
+        let third = allocator.acquire(2).unwrap();
+        assert_eq!(third.node_id(), 2);
+        assert_eq!(allocator.acquire(2).unwrap().node_id(), 3);
+        assert!(allocator.acquire(1).is_err());
+        drop((second, third));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+//! Callbacks invoked by `generate_id` on anomalies, registered with
+//! `SequenceProperties::set_observer`, e.g. to trip a circuit breaker or
+//! page on-call.
+use std::cell::Cell;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Receives the anomalies detected while generating IDs. Every method does
+/// nothing by default, so an observer only implements those it reacts to.
+/// Called on the thread generating the ID, which waits for it to return.
+pub trait GeneratorObserver: Send + Sync {
+    fn clock_regression(&self, _event: &ClockRegression) {}
+    fn sequence_exhausted(&self, _event: &SequenceExhausted) {}
+    fn long_wait(&self, _event: &LongWait) {}
+    fn timestamp_overflow_approaching(&self, _event: &TimestampOverflowApproaching) {}
+}
+
+/// The system clock was found behind the last used timestamp. Timestamps
+/// are in ticks since the custom epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockRegression {
+    pub node_id: u16,
+    pub current_timestamp: u64,
+    pub last_timestamp: u64,
+    pub behind: Duration,
+}
+
+/// Every sequence number of a tick was used, generation waits for the next
+/// tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceExhausted {
+    pub node_id: u16,
+    pub timestamp: u64,
+    pub max_sequence: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitReason {
+    ClockRegression,
+    SequenceExhausted,
+}
+
+/// Generation waited longer than `ObserverThresholds::long_wait`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LongWait {
+    pub node_id: u16,
+    pub reason: WaitReason,
+    pub waited: Duration,
+    pub threshold: Duration,
+}
+
+/// The timestamp field overflows in less than
+/// `ObserverThresholds::overflow_headroom`. Reported once per generator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampOverflowApproaching {
+    pub node_id: u16,
+    pub timestamp: u64,
+    pub max_timestamp: u64,
+    pub headroom: Duration,
+}
+
+/// When waits and the timestamp headroom are worth reporting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObserverThresholds {
+    pub long_wait: Duration,
+    pub overflow_headroom: Duration,
+}
+
+impl Default for ObserverThresholds {
+    /// Waits over 10 ms, less than 365 days left until overflow.
+    fn default() -> Self {
+        ObserverThresholds {
+            long_wait: Duration::from_millis(10),
+            overflow_headroom: Duration::from_secs(365 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// An observer registered on `SequenceProperties`.
+pub(crate) struct Observer {
+    pub(crate) observer: Arc<dyn GeneratorObserver>,
+    pub(crate) thresholds: ObserverThresholds,
+    overflow_reported: Cell<bool>,
+}
+
+impl Observer {
+    pub(crate) fn new(
+        observer: Arc<dyn GeneratorObserver>,
+        thresholds: ObserverThresholds,
+    ) -> Self {
+        Observer {
+            observer,
+            thresholds,
+            overflow_reported: Cell::new(false),
+        }
+    }
+    pub(crate) fn waited(&self, node_id: u16, reason: WaitReason, waited: Duration) {
+        if waited > self.thresholds.long_wait {
+            self.observer.long_wait(&LongWait {
+                node_id,
+                reason,
+                waited,
+                threshold: self.thresholds.long_wait,
+            });
+        }
+    }
+    pub(crate) fn headroom(&self, event: TimestampOverflowApproaching) {
+        if event.headroom < self.thresholds.overflow_headroom && !self.overflow_reported.get() {
+            self.overflow_reported.set(true);
+            self.observer.timestamp_overflow_approaching(&event);
+        }
+    }
+}
+
+impl fmt::Debug for Observer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Observer")
+            .field("thresholds", &self.thresholds)
+            .field("overflow_reported", &self.overflow_reported.get())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn observe_exhaustion_and_overflow() {
+        use super::*;
+        use crate::sequence_generator::{generate_id, SequenceProperties};
+        use std::sync::Mutex;
+        use std::time::SystemTime;
+        #[derive(Default)]
+        struct Recorder {
+            exhausted: Mutex<Vec<SequenceExhausted>>,
+            waits: Mutex<Vec<LongWait>>,
+            overflows: Mutex<Vec<TimestampOverflowApproaching>>,
+        }
+        impl GeneratorObserver for Recorder {
+            fn sequence_exhausted(&self, event: &SequenceExhausted) {
+                self.exhausted.lock().unwrap().push(*event);
+            }
+            fn long_wait(&self, event: &LongWait) {
+                self.waits.lock().unwrap().push(*event);
+            }
+            fn timestamp_overflow_approaching(&self, event: &TimestampOverflowApproaching) {
+                self.overflows.lock().unwrap().push(*event);
+            }
+        }
+        let recorder = Arc::new(Recorder::default());
+        // 40 bits of milliseconds overflow in about 34 years
+        let mut properties = SequenceProperties::new(SystemTime::now(), 16, 5, 1, 3, 7, 1500);
+        properties.set_observer(
+            recorder.clone(),
+            ObserverThresholds {
+                long_wait: Duration::ZERO,
+                overflow_headroom: Duration::from_secs(100 * 365 * 24 * 60 * 60),
+            },
+        );
+        for _ in 0..4 {
+            generate_id(&properties).unwrap();
+        }
+        let exhausted = recorder.exhausted.lock().unwrap();
+        assert_eq!(exhausted.len(), 2);
+        assert_eq!(exhausted[0].node_id, 5);
+        assert_eq!(exhausted[0].max_sequence, 2);
+        let waits = recorder.waits.lock().unwrap();
+        assert_eq!(waits.len(), 2);
+        assert_eq!(waits[0].reason, WaitReason::SequenceExhausted);
+        let overflows = recorder.overflows.lock().unwrap();
+        assert_eq!(overflows.len(), 1);
+        assert_eq!(overflows[0].max_timestamp, (1 << 40) - 1);
+    }
+}
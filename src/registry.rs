@@ -0,0 +1,168 @@
+use crate::layout::{Field, FieldKind};
+use crate::sequence_generator::{generate_id, SequenceGeneratorError, SequenceProperties};
+use std::collections::BTreeMap;
+
+/// Layouts of a deployment indexed by the version stored in their IDs, so
+/// that IDs generated before a layout change can still be decoded. New IDs
+/// are generated with the highest registered version.
+///
+/// ```
+/// use sequence_generator::config::{LayoutVersionConfig, SequenceConfig};
+/// use sequence_generator::registry::LayoutRegistry;
+/// use sequence_generator::sequence_generator::decode_node_id;
+///
+/// let mut config = SequenceConfig::default();
+/// config.layout_version = Some(LayoutVersionConfig { version: 0, bits: 2 });
+/// let mut registry = LayoutRegistry::new();
+/// registry.register(config.build());
+/// config.node_id_bits = 10;
+/// config.sequence_bits = 10;
+/// config.node_id = 513;
+/// config.layout_version = Some(LayoutVersionConfig { version: 1, bits: 2 });
+/// registry.register(config.build());
+///
+/// let id = registry.generate_id().unwrap();
+/// let properties = registry.properties_for(id).unwrap();
+/// assert_eq!(decode_node_id(id, properties), 513);
+/// ```
+#[derive(Debug, Default)]
+pub struct LayoutRegistry {
+    layouts: BTreeMap<u64, SequenceProperties>,
+    version_field: Option<Field>,
+}
+
+impl LayoutRegistry {
+    pub fn new() -> Self {
+        LayoutRegistry::default()
+    }
+    /// Add the layout of `properties` under its layout version. All layouts
+    /// must store the version in the same bits. Panics if the layout has no
+    /// version field, stores it in other bits than the already registered
+    /// layouts, or its version is already registered.
+    pub fn register(&mut self, properties: SequenceProperties) {
+        self.try_register(properties)
+            .unwrap_or_else(|error| panic!("ERROR: {}", error));
+    }
+    /// `LayoutRegistry::register` returning the invalid layouts as
+    /// `SequenceGeneratorError::InvalidConfiguration` instead of panicking.
+    pub fn try_register(
+        &mut self,
+        properties: SequenceProperties,
+    ) -> Result<(), SequenceGeneratorError> {
+        let invalid = |message: String| Err(SequenceGeneratorError::InvalidConfiguration(message));
+        let (version, version_field) = match (
+            properties.layout_version(),
+            properties.layout().field(FieldKind::Version),
+        ) {
+            (Some(version), Some(version_field)) => (version, version_field),
+            _ => return invalid("Layout has no version field.".to_owned()),
+        };
+        if let Some(registered) = self.version_field.as_ref() {
+            if registered != version_field {
+                return invalid(format!(
+                    "Layout version '{}' is stored in {} bits at shift {}, other layouts use {} bits at shift {}.",
+                    version, version_field.bits, version_field.shift, registered.bits, registered.shift
+                ));
+            }
+        }
+        if self.layouts.contains_key(&version) {
+            return invalid(format!(
+                "Layout version '{}' is already registered.",
+                version
+            ));
+        }
+        if self.version_field.is_none() {
+            self.version_field = Some(version_field.clone());
+        }
+        self.layouts.insert(version, properties);
+        Ok(())
+    }
+    /// Properties used to generate new IDs, those with the highest version.
+    pub fn current(&self) -> Option<&SequenceProperties> {
+        self.layouts.values().next_back()
+    }
+    /// Generate an ID with the current layout. Fails with
+    /// `SequenceGeneratorError::InvalidConfiguration` if no layout is
+    /// registered.
+    pub fn generate_id(&self) -> Result<u64, SequenceGeneratorError> {
+        let properties = self.current().ok_or_else(|| {
+            SequenceGeneratorError::InvalidConfiguration("No layout registered.".to_owned())
+        })?;
+        generate_id(properties)
+    }
+    /// Layout version stored in `id`, registered or not.
+    pub fn version_of(&self, id: u64) -> Option<u64> {
+        self.version_field.as_ref().map(|field| field.decode(id))
+    }
+    /// Properties of the layout `id` was generated with, to be used with the
+    /// `decode_*` functions.
+    pub fn properties_for(&self, id: u64) -> Result<&SequenceProperties, SequenceGeneratorError> {
+        let version = self.version_of(id).unwrap_or(0);
+        self.layouts
+            .get(&version)
+            .ok_or(SequenceGeneratorError::UnknownLayoutVersion(version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn decode_by_version() {
+        use super::*;
+        use crate::config::{LayoutVersionConfig, SequenceConfig};
+        use crate::sequence_generator::{decode_node_id, decode_sequence_id};
+        let mut config = SequenceConfig {
+            node_id: 300,
+            layout_version: Some(LayoutVersionConfig {
+                version: 1,
+                bits: 3,
+            }),
+            ..SequenceConfig::default()
+        };
+        let old = config.build();
+        let old_id = generate_id(&old).unwrap();
+        let mut registry = LayoutRegistry::new();
+        assert!(matches!(
+            registry.generate_id(),
+            Err(SequenceGeneratorError::InvalidConfiguration(_))
+        ));
+        registry.register(old);
+        config.node_id_bits = 12;
+        config.sequence_bits = 8;
+        config.node_id = 4000;
+        config.layout_version = Some(LayoutVersionConfig {
+            version: 2,
+            bits: 3,
+        });
+        registry.register(config.build());
+        let new_id = registry.generate_id().unwrap();
+        assert!(matches!(
+            registry.try_register(config.build()),
+            Err(SequenceGeneratorError::InvalidConfiguration(_))
+        ));
+        assert!(matches!(
+            registry.try_register(SequenceConfig::default().build()),
+            Err(SequenceGeneratorError::InvalidConfiguration(_))
+        ));
+        assert_eq!(registry.version_of(old_id), Some(1));
+        assert_eq!(registry.version_of(new_id), Some(2));
+        assert_eq!(
+            decode_node_id(old_id, registry.properties_for(old_id).unwrap()),
+            300
+        );
+        assert_eq!(
+            decode_node_id(new_id, registry.properties_for(new_id).unwrap()),
+            4000
+        );
+        assert_eq!(
+            decode_sequence_id(new_id, registry.properties_for(new_id).unwrap()),
+            0
+        );
+        // Version 5 was never registered
+        let unknown_id = (old_id & !(0b111 << 61)) | (5 << 61);
+        assert!(matches!(
+            registry.properties_for(unknown_id),
+            Err(SequenceGeneratorError::UnknownLayoutVersion(5))
+        ));
+    }
+}
@@ -1,11 +1,134 @@
+use crate::checksum::ChecksumAlgorithm;
+use crate::coordinator::CoordinatorLease;
+use crate::high_water_mark::{HighWaterMark, StartupPolicy};
+use crate::layout::{Field, FieldKind, IdLayout, NodeIdLayout};
+use crate::metrics::GeneratorMetrics;
+#[cfg(unix)]
+use crate::node_id_lease::{NodeIdAllocator, NodeIdLease};
+use crate::observer::{
+    ClockRegression, GeneratorObserver, Observer, ObserverThresholds, SequenceExhausted,
+    TimestampOverflowApproaching, WaitReason,
+};
 use std::borrow::BorrowMut;
 use std::cell::{Cell, RefCell};
-use std::rc::Rc;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
 use std::thread::sleep;
-use std::time::{Duration, SystemTime, SystemTimeError};
+use std::time::{Duration, Instant, SystemTime, SystemTimeError, UNIX_EPOCH};
 
 pub type SequenceGeneratorSystemTimeError = SystemTimeError;
 
+#[derive(Debug)]
+pub enum SequenceGeneratorError {
+    SystemTime(SequenceGeneratorSystemTimeError),
+    /// The layout has no extra field with this name.
+    UnknownField(String),
+    /// The value does not fit in the bits of the field.
+    FieldOverflow {
+        name: String,
+        value: u64,
+        bits: u8,
+    },
+    /// The checksum stored in the ID does not match its other fields.
+    InvalidChecksum {
+        expected: u64,
+        found: u64,
+    },
+    /// No layout was registered for the version stored in the ID.
+    UnknownLayoutVersion(u64),
+    /// Reading or writing the high-water mark state file failed.
+    Io(io::Error),
+    /// The system clock is behind the persisted high-water mark, so IDs
+    /// generated now could duplicate IDs issued before a restart.
+    ClockBehindHighWaterMark {
+        behind: Duration,
+    },
+    /// The node ID lease from the coordinator expired or was lost, another
+    /// node may be given the same node ID.
+    NodeIdLeaseLost(u16),
+    /// Invalid bit widths, layout or field values given to the fallible
+    /// `try_` constructor and setters of `SequenceProperties`.
+    InvalidConfiguration(String),
+}
+
+impl fmt::Display for SequenceGeneratorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceGeneratorError::SystemTime(error) => write!(
+                f,
+                "System time is earlier than the custom epoch by {:?}",
+                error.duration()
+            ),
+            SequenceGeneratorError::UnknownField(name) => {
+                write!(f, "Layout has no extra field named '{}'", name)
+            }
+            SequenceGeneratorError::FieldOverflow { name, value, bits } => write!(
+                f,
+                "Value '{}' of field '{}' does not fit in {} bits",
+                value, name, bits
+            ),
+            SequenceGeneratorError::InvalidChecksum { expected, found } => write!(
+                f,
+                "Corrupted ID, checksum '{}' does not match the expected '{}'",
+                found, expected
+            ),
+            SequenceGeneratorError::UnknownLayoutVersion(version) => {
+                write!(f, "No layout registered for version '{}'", version)
+            }
+            SequenceGeneratorError::Io(error) => {
+                write!(f, "Could not persist the high-water mark: {}", error)
+            }
+            SequenceGeneratorError::ClockBehindHighWaterMark { behind } => write!(
+                f,
+                "System clock is {:?} behind the persisted high-water mark",
+                behind
+            ),
+            SequenceGeneratorError::NodeIdLeaseLost(node_id) => write!(
+                f,
+                "Lease of node ID '{}' from the coordinator was lost",
+                node_id
+            ),
+            SequenceGeneratorError::InvalidConfiguration(message) => f.write_str(message),
+        }
+    }
+}
+
+impl Error for SequenceGeneratorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SequenceGeneratorError::SystemTime(error) => Some(error),
+            SequenceGeneratorError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<SequenceGeneratorSystemTimeError> for SequenceGeneratorError {
+    fn from(error: SequenceGeneratorSystemTimeError) -> Self {
+        SequenceGeneratorError::SystemTime(error)
+    }
+}
+
+impl From<io::Error> for SequenceGeneratorError {
+    fn from(error: io::Error) -> Self {
+        SequenceGeneratorError::Io(error)
+    }
+}
+
+/// Layout errors carry the "ERROR: " prefix of the panic messages, which is
+/// not part of the error itself.
+fn invalid_configuration(message: String) -> SequenceGeneratorError {
+    SequenceGeneratorError::InvalidConfiguration(
+        message
+            .strip_prefix("ERROR: ")
+            .unwrap_or(&message)
+            .to_owned(),
+    )
+}
+
 fn timestamp_from_custom_epoch(
     custom_epoch: SystemTime,
     micros_ten_power: u8,
@@ -23,14 +146,25 @@ pub struct SequenceProperties {
     pub node_id_bits: u8,
     pub sequence_bits: u8,
     pub custom_epoch: SystemTime,
-    current_timestamp: Rc<RefCell<Option<u64>>>,
-    last_timestamp: Rc<RefCell<Option<u64>>>,
+    current_timestamp: RefCell<Option<u64>>,
+    last_timestamp: RefCell<Option<u64>>,
     pub micros_ten_power: u8,
     pub node_id: u16,
     pub sequence: Cell<u16>,
     pub max_sequence: u16,
     pub backoff_cooldown_start_ns: u64,
-    partial_cached_id: Rc<RefCell<Option<u64>>>,
+    partial_cached_id: RefCell<Option<u64>>,
+    layout: IdLayout,
+    node_id_layout: Option<NodeIdLayout>,
+    extra_values: Vec<(String, u64)>,
+    checksum: Option<ChecksumAlgorithm>,
+    layout_version: Option<u64>,
+    high_water_mark: Option<HighWaterMark>,
+    #[cfg(unix)]
+    node_id_lease: Option<NodeIdLease>,
+    coordinator_lease: Option<CoordinatorLease>,
+    metrics: Option<Arc<GeneratorMetrics>>,
+    observer: Option<Observer>,
 }
 
 impl SequenceProperties {
@@ -43,52 +177,93 @@ impl SequenceProperties {
         unused_bits: u8,
         backoff_cooldown_start_ns: u64,
     ) -> Self {
+        SequenceProperties::try_new(
+            custom_epoch,
+            node_id_bits,
+            node_id,
+            sequence_bits,
+            micros_ten_power,
+            unused_bits,
+            backoff_cooldown_start_ns,
+        )
+        .unwrap_or_else(|error| panic!("ERROR: {}", error))
+    }
+    /// `SequenceProperties::new` returning invalid bit widths or
+    /// `micros_ten_power` as `SequenceGeneratorError::InvalidConfiguration`
+    /// instead of panicking.
+    pub fn try_new(
+        custom_epoch: SystemTime,
+        node_id_bits: u8,
+        node_id: u16,
+        sequence_bits: u8,
+        micros_ten_power: u8,
+        unused_bits: u8,
+        backoff_cooldown_start_ns: u64,
+    ) -> Result<Self, SequenceGeneratorError> {
+        let invalid = |message: String| Err(SequenceGeneratorError::InvalidConfiguration(message));
         if unused_bits > 7 {
-            panic!(
-                "ERROR: unused_bits '{}' is larger than the maximum value of 7.",
+            return invalid(format!(
+                "unused_bits '{}' is larger than the maximum value of 7.",
                 unused_bits
-            )
+            ));
         }
         if sequence_bits > 16 {
-            panic!(
-                "ERROR: sequence_bits '{}' is larger than the maximum value of 16.",
+            return invalid(format!(
+                "sequence_bits '{}' is larger than the maximum value of 16.",
                 sequence_bits
-            )
+            ));
         }
         if sequence_bits == 0 {
-            panic!(
-                "ERROR: sequence_bits '{}' must be larger or equal than 1.",
+            return invalid(format!(
+                "sequence_bits '{}' must be larger or equal than 1.",
                 sequence_bits
-            )
+            ));
         }
         if node_id_bits > 16 {
-            panic!(
-                "ERROR: node_id_bits '{}' is larger than the maximum value of 16.",
+            return invalid(format!(
+                "node_id_bits '{}' is larger than the maximum value of 16.",
                 node_id_bits
-            )
+            ));
         }
         if node_id_bits == 0 {
-            panic!(
-                "ERROR: node_id_bits '{}' must be larger or equal than 1.",
+            return invalid(format!(
+                "node_id_bits '{}' must be larger or equal than 1.",
                 node_id_bits
-            )
+            ));
+        }
+        // Ticks of 10^19 microseconds no longer fit in 64 bits
+        if micros_ten_power > 18 {
+            return invalid(format!(
+                "micros_ten_power '{}' is larger than the maximum value of 18.",
+                micros_ten_power
+            ));
         }
-        let timestamp_bits = (64_u8)
+        let timestamp_bits = match (64_u8)
             .checked_sub(sequence_bits)
-            .unwrap_or_else(|| {panic!(
-                "ERROR: Sum of bits is too large, maximum value 64. Sequence bits '{}'", sequence_bits)})
-            .checked_sub(node_id_bits)
-            .unwrap_or_else(|| {panic!(
-                "ERROR: Sum of bits is too large, maximum value 64. Node ID bits '{}', Sequence bits '{}'",
-                node_id_bits, sequence_bits
-            )})
-            .checked_sub(unused_bits)
-            .unwrap_or_else(|| {panic!(
-                "ERROR: Sum of bits is too large, maximum value 64. Unused bits '{}', Sequence bits '{}', Node ID bits '{}'", 
+            .and_then(|bits| bits.checked_sub(node_id_bits))
+            .and_then(|bits| bits.checked_sub(unused_bits))
+        {
+            Some(timestamp_bits) => timestamp_bits,
+            None => return invalid(format!(
+                "Sum of bits is too large, maximum value 64. Unused bits '{}', Sequence bits '{}', Node ID bits '{}'",
                 unused_bits, sequence_bits, node_id_bits
-            )});
+            )),
+        };
 
-        SequenceProperties {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            node_id,
+            node_id_bits,
+            sequence_bits,
+            timestamp_bits,
+            unused_bits,
+            micros_ten_power,
+            custom_epoch_micros = custom_epoch
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |epoch| epoch.as_micros() as u64),
+            "sequence generator configured"
+        );
+        Ok(SequenceProperties {
             custom_epoch,
             timestamp_bits,
             node_id_bits,
@@ -97,48 +272,409 @@ impl SequenceProperties {
             node_id,
             unused_bits,
             sequence: Cell::new(0_u16),
-            current_timestamp: Rc::new(RefCell::new(None)),
-            last_timestamp: Rc::new(RefCell::new(None)),
+            current_timestamp: RefCell::new(None),
+            last_timestamp: RefCell::new(None),
             max_sequence: (2_u16).pow(sequence_bits.into()),
             backoff_cooldown_start_ns,
-            partial_cached_id: Rc::new(RefCell::new(None)),
+            partial_cached_id: RefCell::new(None),
+            layout: IdLayout::new(
+                unused_bits,
+                &[
+                    (FieldKind::Timestamp, timestamp_bits),
+                    (FieldKind::Sequence, sequence_bits),
+                    (FieldKind::NodeId, node_id_bits),
+                ],
+            ),
+            node_id_layout: None,
+            extra_values: Vec::new(),
+            checksum: None,
+            layout_version: None,
+            high_water_mark: None,
+            #[cfg(unix)]
+            node_id_lease: None,
+            coordinator_lease: None,
+            metrics: None,
+            observer: None,
+        })
+    }
+    pub fn layout(&self) -> &IdLayout {
+        &self.layout
+    }
+    pub fn node_id_layout(&self) -> Option<&NodeIdLayout> {
+        self.node_id_layout.as_ref()
+    }
+    /// Split the node ID into named subfields listed from left-most to
+    /// right-most bits, e.g. `&[("datacenter", 5), ("worker", 5)]`. Their
+    /// widths must add up to `node_id_bits`.
+    pub fn set_node_subfields(&mut self, subfields: &[(&str, u8)]) {
+        self.try_set_node_subfields(subfields)
+            .unwrap_or_else(|error| panic!("ERROR: {}", error));
+    }
+    pub fn try_set_node_subfields(
+        &mut self,
+        subfields: &[(&str, u8)],
+    ) -> Result<(), SequenceGeneratorError> {
+        self.node_id_layout = Some(
+            NodeIdLayout::try_new(self.node_id_bits, subfields).map_err(invalid_configuration)?,
+        );
+        Ok(())
+    }
+    /// Set the value of one node ID subfield, keeping the rest of the node ID.
+    pub fn set_node_subfield(&mut self, name: &str, value: u16) {
+        self.try_set_node_subfield(name, value)
+            .unwrap_or_else(|error| panic!("ERROR: {}", error));
+    }
+    pub fn try_set_node_subfield(
+        &mut self,
+        name: &str,
+        value: u16,
+    ) -> Result<(), SequenceGeneratorError> {
+        let node_id_layout = self.node_id_layout.as_ref().ok_or_else(|| {
+            SequenceGeneratorError::InvalidConfiguration(format!(
+                "Cannot set node ID subfield '{}', no subfields were defined.",
+                name
+            ))
+        })?;
+        self.node_id = node_id_layout
+            .set(self.node_id, name, value)
+            .map_err(invalid_configuration)?;
+        if self.partial_cached_id.borrow().is_some() {
+            cache_partial_id(self);
+        }
+        Ok(())
+    }
+    /// Rearrange the fields of the ID, listed from left-most to right-most
+    /// bits. By default the sequence is placed before the node ID, while
+    /// Twitter and Discord store the sequence in the right-most bits.
+    pub fn set_field_order(&mut self, order: &[&str]) {
+        self.try_set_field_order(order)
+            .unwrap_or_else(|error| panic!("ERROR: {}", error));
+    }
+    pub fn try_set_field_order(&mut self, order: &[&str]) -> Result<(), SequenceGeneratorError> {
+        self.layout = self
+            .layout
+            .try_reordered(order)
+            .map_err(invalid_configuration)?;
+        if self.partial_cached_id.borrow().is_some() {
+            cache_partial_id(self);
         }
+        Ok(())
+    }
+    /// Add an extra field, such as an entity type tag or a shard number,
+    /// after the extra fields already added, or right after the timestamp
+    /// for the first one. Its bits are taken from the timestamp and
+    /// its value is 0 unless set with `set_field_value` or given on each call
+    /// to `generate_id_with`. Use `set_field_order` to move it elsewhere.
+    pub fn add_field(&mut self, name: &str, bits: u8) {
+        self.try_add_field(name, bits)
+            .unwrap_or_else(|error| panic!("ERROR: {}", error));
+    }
+    pub fn try_add_field(&mut self, name: &str, bits: u8) -> Result<(), SequenceGeneratorError> {
+        // Also the spellings a field order accepts, e.g. 'node' or 'Timestamp'
+        if name.parse::<FieldKind>().is_ok() {
+            return Err(SequenceGeneratorError::InvalidConfiguration(format!(
+                "Extra field name '{}' is reserved for a built-in field.",
+                name
+            )));
+        }
+        let fields = self.layout.fields();
+        let position = fields
+            .iter()
+            .rposition(|field| field.kind == FieldKind::Extra)
+            .or_else(|| {
+                fields
+                    .iter()
+                    .position(|field| field.kind == FieldKind::Timestamp)
+            })
+            .unwrap()
+            + 1;
+        self.insert_field(position, name, bits)?;
+        self.extra_values.push((name.to_owned(), 0));
+        if self.partial_cached_id.borrow().is_some() {
+            cache_partial_id(self);
+        }
+        Ok(())
+    }
+    /// Insert a new field at `position`, counted from the left-most field of
+    /// the layout, taking its bits from the timestamp.
+    fn insert_field(
+        &mut self,
+        position: usize,
+        name: &str,
+        bits: u8,
+    ) -> Result<(), SequenceGeneratorError> {
+        let timestamp_bits = self
+            .timestamp_bits
+            .checked_sub(bits)
+            .filter(|timestamp_bits| *timestamp_bits > 0)
+            .ok_or_else(|| {
+                SequenceGeneratorError::InvalidConfiguration(format!(
+                    "Field '{}' of {} bits leaves no bits for the timestamp ({} bits).",
+                    name, bits, self.timestamp_bits
+                ))
+            })?;
+        let mut fields = self.layout.specs();
+        for field in fields.iter_mut() {
+            if field.0 == FieldKind::Timestamp.name() {
+                field.1 = timestamp_bits;
+            }
+        }
+        fields.insert(position, (name, bits));
+        self.layout =
+            IdLayout::try_named(self.unused_bits, &fields).map_err(invalid_configuration)?;
+        self.timestamp_bits = timestamp_bits;
+        Ok(())
+    }
+    /// Reserve the right-most `bits` of the ID for a checksum of the other
+    /// fields, filled on generation and verified by `validate_id`. Its bits
+    /// are taken from the timestamp.
+    pub fn set_checksum(&mut self, algorithm: ChecksumAlgorithm, bits: u8) {
+        self.try_set_checksum(algorithm, bits)
+            .unwrap_or_else(|error| panic!("ERROR: {}", error));
+    }
+    pub fn try_set_checksum(
+        &mut self,
+        algorithm: ChecksumAlgorithm,
+        bits: u8,
+    ) -> Result<(), SequenceGeneratorError> {
+        if self.checksum.is_some() {
+            return Err(SequenceGeneratorError::InvalidConfiguration(
+                "Checksum field is already defined.".to_owned(),
+            ));
+        }
+        if bits < algorithm.min_bits() || bits > algorithm.max_bits() {
+            return Err(SequenceGeneratorError::InvalidConfiguration(format!(
+                "Checksum '{}' requires between {} and {} bits, got '{}'.",
+                algorithm,
+                algorithm.min_bits(),
+                algorithm.max_bits(),
+                bits
+            )));
+        }
+        let position = self.layout.fields().len();
+        self.insert_field(position, FieldKind::Checksum.name(), bits)?;
+        self.checksum = Some(algorithm);
+        if self.partial_cached_id.borrow().is_some() {
+            cache_partial_id(self);
+        }
+        Ok(())
+    }
+    pub fn checksum(&self) -> Option<ChecksumAlgorithm> {
+        self.checksum
+    }
+    /// Store `version` in the left-most `bits` of every ID, so that a
+    /// `LayoutRegistry` can select the layout needed to decode it. Its bits
+    /// are taken from the timestamp.
+    pub fn set_layout_version(&mut self, version: u64, bits: u8) {
+        self.try_set_layout_version(version, bits)
+            .unwrap_or_else(|error| panic!("ERROR: {}", error));
+    }
+    pub fn try_set_layout_version(
+        &mut self,
+        version: u64,
+        bits: u8,
+    ) -> Result<(), SequenceGeneratorError> {
+        if self.layout_version.is_some() {
+            return Err(SequenceGeneratorError::InvalidConfiguration(
+                "Layout version field is already defined.".to_owned(),
+            ));
+        }
+        if bits == 0 || bits > 16 || version >= (1_u64 << bits) {
+            return Err(SequenceGeneratorError::InvalidConfiguration(format!(
+                "Layout version '{}' does not fit in {} bits (between 1 and 16).",
+                version, bits
+            )));
+        }
+        self.insert_field(0, FieldKind::Version.name(), bits)?;
+        self.layout_version = Some(version);
+        if self.partial_cached_id.borrow().is_some() {
+            cache_partial_id(self);
+        }
+        Ok(())
+    }
+    pub fn layout_version(&self) -> Option<u64> {
+        self.layout_version
+    }
+    /// Persist the last issued timestamp to a state file so that a restart
+    /// with a regressed clock does not reissue IDs. If the clock is behind
+    /// the mark already in the file, either fail or sleep until it passes,
+    /// following the policy of `mark`.
+    pub fn set_high_water_mark(
+        &mut self,
+        mark: HighWaterMark,
+    ) -> Result<(), SequenceGeneratorError> {
+        if let Some(persisted_micros) = mark.load()? {
+            loop {
+                let now_micros = SystemTime::now().duration_since(UNIX_EPOCH)?.as_micros() as u64;
+                if now_micros >= persisted_micros {
+                    break;
+                }
+                let behind = Duration::from_micros(persisted_micros - now_micros);
+                match mark.policy {
+                    StartupPolicy::Refuse => {
+                        return Err(SequenceGeneratorError::ClockBehindHighWaterMark { behind })
+                    }
+                    StartupPolicy::Wait => sleep(behind),
+                }
+            }
+        }
+        self.high_water_mark = Some(mark);
+        Ok(())
+    }
+    pub fn high_water_mark(&self) -> Option<&HighWaterMark> {
+        self.high_water_mark.as_ref()
+    }
+    /// Use the lowest node ID not leased by another process sharing the
+    /// directory of `allocator`. The lease is held until these properties
+    /// are dropped or another node ID is leased.
+    #[cfg(unix)]
+    pub fn lease_node_id(
+        &mut self,
+        allocator: &NodeIdAllocator,
+    ) -> Result<u16, SequenceGeneratorError> {
+        let lease = allocator.acquire(self.node_id_bits)?;
+        self.node_id = lease.node_id();
+        self.node_id_lease = Some(lease);
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            node_id = self.node_id,
+            "node ID leased from a lease directory"
+        );
+        if self.partial_cached_id.borrow().is_some() {
+            cache_partial_id(self);
+        }
+        Ok(self.node_id)
+    }
+    #[cfg(unix)]
+    pub fn node_id_lease(&self) -> Option<&NodeIdLease> {
+        self.node_id_lease.as_ref()
+    }
+    /// Use the node ID leased from a coordinator. Generating IDs fails with
+    /// `SequenceGeneratorError::NodeIdLeaseLost` once the lease is lost.
+    pub fn set_coordinator_lease(&mut self, lease: CoordinatorLease) {
+        self.try_set_coordinator_lease(lease)
+            .unwrap_or_else(|error| panic!("ERROR: {}", error));
+    }
+    pub fn try_set_coordinator_lease(
+        &mut self,
+        lease: CoordinatorLease,
+    ) -> Result<(), SequenceGeneratorError> {
+        if self.node_id_bits < 16 && lease.node_id() >> self.node_id_bits != 0 {
+            return Err(SequenceGeneratorError::InvalidConfiguration(format!(
+                "Leased node ID '{}' does not fit in {} node ID bits.",
+                lease.node_id(),
+                self.node_id_bits
+            )));
+        }
+        self.node_id = lease.node_id();
+        self.coordinator_lease = Some(lease);
+        #[cfg(feature = "tracing")]
+        tracing::info!(node_id = self.node_id, "node ID leased from a coordinator");
+        if self.partial_cached_id.borrow().is_some() {
+            cache_partial_id(self);
+        }
+        Ok(())
+    }
+    pub fn coordinator_lease(&self) -> Option<&CoordinatorLease> {
+        self.coordinator_lease.as_ref()
+    }
+    /// Record IDs issued, sequence exhaustion waits, clock regressions and
+    /// the timestamp headroom left into `metrics`, shared with whoever
+    /// exposes them.
+    pub fn set_metrics(&mut self, metrics: Arc<GeneratorMetrics>) {
+        self.metrics = Some(metrics);
+    }
+    pub fn metrics(&self) -> Option<&Arc<GeneratorMetrics>> {
+        self.metrics.as_ref()
+    }
+    /// Call `observer` on clock regressions, sequence exhaustion, waits
+    /// longer than `thresholds.long_wait` and once the timestamp field
+    /// overflows in less than `thresholds.overflow_headroom`.
+    pub fn set_observer(
+        &mut self,
+        observer: Arc<dyn GeneratorObserver>,
+        thresholds: ObserverThresholds,
+    ) {
+        self.observer = Some(Observer::new(observer, thresholds));
+    }
+    /// Value of an extra field as set with `set_field_value`.
+    pub fn field_value(&self, name: &str) -> Option<u64> {
+        self.extra_values
+            .iter()
+            .find(|(field_name, _)| field_name == name)
+            .map(|(_, value)| *value)
+    }
+    /// Value of an extra field used by `generate_id` and by `generate_id_with`
+    /// when the call does not override it.
+    pub fn set_field_value(&mut self, name: &str, value: u64) {
+        self.try_set_field_value(name, value)
+            .unwrap_or_else(|error| panic!("ERROR: {}", error));
+    }
+    pub fn try_set_field_value(
+        &mut self,
+        name: &str,
+        value: u64,
+    ) -> Result<(), SequenceGeneratorError> {
+        check_field_value(self, name, value)?;
+        if let Some(entry) = self
+            .extra_values
+            .iter_mut()
+            .find(|(field_name, _)| field_name == name)
+        {
+            entry.1 = value;
+        }
+        if self.partial_cached_id.borrow().is_some() {
+            cache_partial_id(self);
+        }
+        Ok(())
     }
     pub fn set_last_timestamp(&self, timestamp: &mut Option<u64>) {
         if let Some(last_timestamp) = timestamp.take() {
-            let _ = self
-                .last_timestamp
-                .as_ref()
-                .borrow_mut()
-                .insert(last_timestamp);
+            let _ = self.last_timestamp.borrow_mut().insert(last_timestamp);
         }
     }
-    pub fn set_current_timestamp(&self) {
-        let _ = self.current_timestamp.as_ref().borrow_mut().insert(timestamp_from_custom_epoch(
-            self.custom_epoch,
-            self.micros_ten_power,
-        ).unwrap_or_else(|error| {panic!("ERROR: Could not calculate current timestamp from custom epoch {:?} and micros power of {:?}. Error: {}",
-        self.custom_epoch, self.micros_ten_power, error)}));
+    /// Fails with `SequenceGeneratorError::SystemTime` while the system
+    /// clock is earlier than the custom epoch.
+    pub fn set_current_timestamp(&self) -> Result<(), SequenceGeneratorError> {
+        let current_timestamp =
+            timestamp_from_custom_epoch(self.custom_epoch, self.micros_ten_power)?;
+        let _ = self
+            .current_timestamp
+            .borrow_mut()
+            .insert(current_timestamp);
+        Ok(())
     }
     pub fn set_partial_cached_id(&self, cached_id: &mut Option<u64>) {
         let _ = self
             .partial_cached_id
-            .as_ref()
             .borrow_mut()
             .insert(cached_id.take().unwrap());
     }
 }
 
-pub fn generate_id(
-    properties: &SequenceProperties,
-) -> Result<u64, SequenceGeneratorSystemTimeError> {
-    properties.set_last_timestamp(&mut properties.current_timestamp.clone().take().take());
-    properties.set_current_timestamp();
+pub fn generate_id(properties: &SequenceProperties) -> Result<u64, SequenceGeneratorError> {
+    #[allow(clippy::needless_option_take)]
+    properties.set_last_timestamp(&mut properties.current_timestamp.take().take());
+    properties.set_current_timestamp()?;
     if let Some(last_timestamp) = properties.last_timestamp.take() {
         let current_timestamp = properties.current_timestamp.borrow().unwrap();
         if current_timestamp < last_timestamp {
-            println!("ERROR: System Clock moved backwards. Current timestamp '{}' is earlier than last registered '{}'.", 
-                current_timestamp, last_timestamp);
+            let behind = ticks_duration(
+                last_timestamp - current_timestamp,
+                properties.micros_ten_power,
+            );
+            if let Some(metrics) = properties.metrics.as_ref() {
+                metrics.record_clock_regression(behind);
+            }
+            if let Some(observer) = properties.observer.as_ref() {
+                observer.observer.clock_regression(&ClockRegression {
+                    node_id: properties.node_id,
+                    current_timestamp,
+                    last_timestamp,
+                    behind,
+                });
+            }
+            let wait_started = Instant::now();
             if properties.sequence.get() == properties.max_sequence {
                 wait_next_timestamp(
                     last_timestamp,
@@ -156,26 +692,170 @@ pub fn generate_id(
                     properties.backoff_cooldown_start_ns,
                 )?;
             }
-            properties.set_current_timestamp();
+            let waited = wait_started.elapsed();
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                current_timestamp,
+                last_timestamp,
+                node_id = properties.node_id,
+                wait_micros = waited.as_micros() as u64,
+                "system clock moved backwards, waited until the last timestamp"
+            );
+            if let Some(observer) = properties.observer.as_ref() {
+                observer.waited(properties.node_id, WaitReason::ClockRegression, waited);
+            }
+            properties.set_current_timestamp()?;
         } else if properties.current_timestamp.borrow().unwrap() != last_timestamp {
             properties.sequence.set(0);
         }
     }
+    persist_high_water_mark(properties)?;
+    if let Some(lease) = properties.coordinator_lease.as_ref() {
+        if !lease.is_valid() {
+            return Err(SequenceGeneratorError::NodeIdLeaseLost(lease.node_id()));
+        }
+    }
     let new_id = to_id(properties);
     let new_sequence = properties.sequence.get() + 1;
     properties.sequence.set(new_sequence);
+    if properties.metrics.is_some() || properties.observer.is_some() {
+        let max_timestamp = field(properties, FieldKind::Timestamp).max_value();
+        let current_timestamp = properties.current_timestamp.borrow().unwrap();
+        let headroom = ticks_duration(
+            max_timestamp.saturating_sub(current_timestamp),
+            properties.micros_ten_power,
+        );
+        if let Some(metrics) = properties.metrics.as_ref() {
+            metrics.record_id(headroom);
+        }
+        if let Some(observer) = properties.observer.as_ref() {
+            observer.headroom(TimestampOverflowApproaching {
+                node_id: properties.node_id,
+                timestamp: current_timestamp,
+                max_timestamp,
+                headroom,
+            });
+        }
+    }
     if new_sequence == properties.max_sequence {
+        let current_timestamp = properties.current_timestamp.borrow().unwrap();
+        if let Some(observer) = properties.observer.as_ref() {
+            observer.observer.sequence_exhausted(&SequenceExhausted {
+                node_id: properties.node_id,
+                timestamp: current_timestamp,
+                max_sequence: properties.max_sequence,
+            });
+        }
+        let wait_started = Instant::now();
         wait_next_timestamp(
-            properties.current_timestamp.borrow().unwrap(),
+            current_timestamp,
             properties.custom_epoch,
             properties.micros_ten_power,
             properties.backoff_cooldown_start_ns,
         )?;
-        properties.set_current_timestamp();
+        let waited = wait_started.elapsed();
+        if let Some(metrics) = properties.metrics.as_ref() {
+            metrics.record_exhaustion_wait(waited);
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            current_timestamp,
+            node_id = properties.node_id,
+            wait_micros = waited.as_micros() as u64,
+            "sequence exhausted, waited for the next timestamp"
+        );
+        if let Some(observer) = properties.observer.as_ref() {
+            observer.waited(properties.node_id, WaitReason::SequenceExhausted, waited);
+        }
+        properties.set_current_timestamp()?;
         // After timestamp changed reset to start a new sequence
         properties.sequence.set(0);
     }
-    Ok(new_id)
+    Ok(with_checksum(properties, new_id))
+}
+
+fn ticks_duration(ticks: u64, micros_ten_power: u8) -> Duration {
+    Duration::from_micros(ticks.saturating_mul(10_u64.pow(micros_ten_power.into())))
+}
+
+/// Write the end of the current tick to the high-water mark state file, if
+/// any, before issuing IDs with it.
+fn persist_high_water_mark(properties: &SequenceProperties) -> Result<(), SequenceGeneratorError> {
+    if let Some(mark) = properties.high_water_mark.as_ref() {
+        let tick_micros = 10_u64.pow(properties.micros_ten_power.into());
+        let epoch_micros = properties
+            .custom_epoch
+            .duration_since(UNIX_EPOCH)?
+            .as_micros() as u64;
+        let tick_end_micros =
+            epoch_micros + (properties.current_timestamp.borrow().unwrap() + 1) * tick_micros;
+        mark.advance(tick_end_micros)?;
+    }
+    Ok(())
+}
+
+/// Generate an ID overriding the values of extra fields for this call only,
+/// e.g. `generate_id_with(&properties, &[("type", 3)])`. Values are checked
+/// before generating, so an invalid value does not consume a sequence number.
+pub fn generate_id_with(
+    properties: &SequenceProperties,
+    values: &[(&str, u64)],
+) -> Result<u64, SequenceGeneratorError> {
+    for (name, value) in values {
+        check_field_value(properties, name, *value)?;
+    }
+    let mut id = generate_id(properties)?;
+    for (name, value) in values {
+        let field = properties.layout.field_named(name).unwrap();
+        id = (id & !field.encode(u64::MAX)) | field.encode(*value);
+    }
+    Ok(with_checksum(properties, id))
+}
+
+/// Verify the checksum field of an ID, reporting IDs that were mistyped or
+/// otherwise corrupted. Always succeeds if the layout has no checksum.
+pub fn validate_id(id: u64, properties: &SequenceProperties) -> Result<(), SequenceGeneratorError> {
+    let expected = with_checksum(properties, id);
+    if expected != id {
+        let checksum_field = field(properties, FieldKind::Checksum);
+        return Err(SequenceGeneratorError::InvalidChecksum {
+            expected: checksum_field.decode(expected),
+            found: checksum_field.decode(id),
+        });
+    }
+    Ok(())
+}
+
+/// Replace the checksum bits of `id` with the checksum of its other bits.
+fn with_checksum(properties: &SequenceProperties, id: u64) -> u64 {
+    match properties.checksum {
+        Some(algorithm) => {
+            let checksum_field = field(properties, FieldKind::Checksum);
+            let payload = id & !checksum_field.encode(u64::MAX);
+            payload | checksum_field.encode(algorithm.checksum(payload, checksum_field.bits))
+        }
+        None => id,
+    }
+}
+
+fn check_field_value(
+    properties: &SequenceProperties,
+    name: &str,
+    value: u64,
+) -> Result<(), SequenceGeneratorError> {
+    let field = properties
+        .layout
+        .field_named(name)
+        .filter(|field| field.kind == FieldKind::Extra)
+        .ok_or_else(|| SequenceGeneratorError::UnknownField(name.to_owned()))?;
+    if value > field.max_value() {
+        return Err(SequenceGeneratorError::FieldOverflow {
+            name: name.to_owned(),
+            value,
+            bits: field.bits,
+        });
+    }
+    Ok(())
 }
 
 fn wait_next_timestamp(
@@ -226,9 +906,16 @@ fn wait_until_last_timestamp(
     Ok(())
 }
 
+fn field(properties: &SequenceProperties, kind: FieldKind) -> &Field {
+    properties
+        .layout
+        .field(kind)
+        .unwrap_or_else(|| panic!("ERROR: Layout is missing the '{}' field.", kind))
+}
+
 fn to_id_cached(properties: &SequenceProperties) -> u64 {
-    let mut id = properties.partial_cached_id.as_ref().borrow().unwrap();
-    id |= (properties.sequence.get() as u64) << properties.node_id_bits;
+    let mut id = properties.partial_cached_id.borrow().unwrap();
+    id |= field(properties, FieldKind::Sequence).encode(properties.sequence.get() as u64);
     id
 }
 
@@ -240,15 +927,47 @@ fn to_id(properties: &SequenceProperties) -> u64 {
 }
 
 fn cache_partial_id(properties: &SequenceProperties) {
-    let timestamp_shift_bits = properties.node_id_bits + properties.sequence_bits;
-    let mut id = properties.current_timestamp.borrow().unwrap() << timestamp_shift_bits;
-    id |= properties.node_id as u64;
+    let mut id = field(properties, FieldKind::Timestamp)
+        .encode(properties.current_timestamp.borrow().unwrap());
+    id |= field(properties, FieldKind::NodeId).encode(properties.node_id as u64);
+    if let Some(version) = properties.layout_version {
+        id |= field(properties, FieldKind::Version).encode(version);
+    }
+    for (name, value) in properties.extra_values.iter() {
+        if let Some(field) = properties.layout.field_named(name) {
+            id |= field.encode(*value);
+        }
+    }
     properties.set_partial_cached_id(Some(id).borrow_mut());
 }
 
+/// Smallest ID whose timestamp is the tick of `time`, with every field
+/// after the timestamp set to 0, e.g. to select IDs by time range. IDs
+/// generated at or after `time` are greater or equal, except those of the
+/// same tick generated earlier.
+pub fn min_id_at(
+    time: SystemTime,
+    properties: &SequenceProperties,
+) -> Result<u64, SequenceGeneratorError> {
+    let micros = time.duration_since(properties.custom_epoch)?.as_micros();
+    let ticks = micros / 10_u128.pow(properties.micros_ten_power.into());
+    let timestamp_field = field(properties, FieldKind::Timestamp);
+    if ticks > u128::from(timestamp_field.max_value()) {
+        return Err(SequenceGeneratorError::FieldOverflow {
+            name: timestamp_field.name.clone(),
+            value: u64::try_from(ticks).unwrap_or(u64::MAX),
+            bits: timestamp_field.bits,
+        });
+    }
+    let mut id = timestamp_field.encode(ticks as u64);
+    if let Some(version) = properties.layout_version {
+        id |= field(properties, FieldKind::Version).encode(version);
+    }
+    Ok(id)
+}
+
 pub fn decode_timestamp_micros(id: u64, properties: &SequenceProperties) -> u64 {
-    let id_timestamp_custom_epoch = (id << (properties.unused_bits))
-        >> (properties.node_id_bits + properties.sequence_bits + properties.unused_bits);
+    let id_timestamp_custom_epoch = field(properties, FieldKind::Timestamp).decode(id);
     let timestamp_micros =
         id_timestamp_custom_epoch * (10_u64).pow(properties.micros_ten_power as u32);
     properties
@@ -261,15 +980,55 @@ pub fn decode_timestamp_micros(id: u64, properties: &SequenceProperties) -> u64
 }
 
 pub fn decode_node_id(id: u64, properties: &SequenceProperties) -> u16 {
-    ((id << (properties.unused_bits + properties.timestamp_bits + properties.sequence_bits))
-        >> (properties.sequence_bits + properties.timestamp_bits + properties.unused_bits))
-        as u16
+    field(properties, FieldKind::NodeId).decode(id) as u16
+}
+
+/// Values of the named node ID subfields, from left-most to right-most.
+/// Empty if the node ID was not split into subfields.
+pub fn decode_node_subfields(id: u64, properties: &SequenceProperties) -> Vec<(String, u16)> {
+    properties
+        .node_id_layout
+        .as_ref()
+        .map(|node_id_layout| node_id_layout.split(decode_node_id(id, properties)))
+        .unwrap_or_default()
+}
+
+pub fn decode_node_subfield(id: u64, properties: &SequenceProperties, name: &str) -> Option<u16> {
+    properties
+        .node_id_layout
+        .as_ref()
+        .and_then(|node_id_layout| node_id_layout.get(decode_node_id(id, properties), name))
 }
 
 pub fn decode_sequence_id(id: u64, properties: &SequenceProperties) -> u16 {
-    ((id << (properties.unused_bits + properties.timestamp_bits))
-        >> (properties.unused_bits + properties.timestamp_bits + properties.node_id_bits))
-        as u16
+    field(properties, FieldKind::Sequence).decode(id) as u16
+}
+
+/// Layout version stored in the ID, if the layout has a version field.
+pub fn decode_layout_version(id: u64, properties: &SequenceProperties) -> Option<u64> {
+    properties
+        .layout
+        .field(FieldKind::Version)
+        .map(|field| field.decode(id))
+}
+
+/// Raw value of any field of the layout by name, including extra fields.
+pub fn decode_field(id: u64, properties: &SequenceProperties, name: &str) -> Option<u64> {
+    properties
+        .layout
+        .field_named(name)
+        .map(|field| field.decode(id))
+}
+
+/// Raw values of every field, from left-most to right-most. The timestamp is
+/// given in ticks of `10^micros_ten_power` microseconds since the custom epoch.
+pub fn decode_fields(id: u64, properties: &SequenceProperties) -> Vec<(String, u64)> {
+    properties
+        .layout
+        .fields()
+        .iter()
+        .map(|field| (field.name.clone(), field.decode(id)))
+        .collect()
 }
 
 #[cfg(test)]
@@ -345,6 +1104,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::expect_fun_call)]
     fn wait_until() {
         // Case where system clock is readjusted 50ms into the past
         // Current sequence wouldn't be exhausted but script cools down
@@ -424,6 +1184,7 @@ mod tests {
         assert!(substracted_times < 35);
     }
     #[test]
+    #[allow(clippy::needless_range_loop)]
     fn gen_id() {
         use super::*;
         use rand::Rng;
@@ -470,13 +1231,12 @@ mod tests {
             backoff_cooldown_start_ns,
         );
         for element in vector_ids.iter_mut() {
-            *element = generate_id(&properties).unwrap_or_else(
-                |error| {
-                    panic!(
-                        "SequenceGeneratorSystemTimeError: Failed to get timestamp from custom epoch {:?}, difference {:?}",
-                        custom_epoch, (error).duration()
-                    )
-                });
+            *element = generate_id(&properties).unwrap_or_else(|error| {
+                panic!(
+                    "SequenceGeneratorError: Failed to get timestamp from custom epoch {:?}, {}",
+                    custom_epoch, error
+                )
+            });
         }
         let decoded_timestamp = decode_timestamp_micros(vector_ids[0], &properties);
         assert!(((decoded_timestamp / 10_000) - (last_timestamp + 1)) < 15);
@@ -492,4 +1252,139 @@ mod tests {
         }
         assert!(properties.current_timestamp.borrow().unwrap() - last_timestamp < 15);
     }
+    #[test]
+    fn gen_id_field_order() {
+        // Twitter/Discord order: the sequence takes the right-most bits and the
+        // node ID is placed right after the timestamp
+        use super::*;
+        let mut properties = SequenceProperties::new(SystemTime::now(), 10, 777, 12, 3, 1, 1500);
+        properties.set_field_order(&["node_id", "sequence"]);
+        let ids: Vec<u64> = (0..3)
+            .map(|_| generate_id(&properties).expect("ERROR: Failed to generate ID."))
+            .collect();
+        for id in ids {
+            assert_eq!(decode_node_id(id, &properties), 777);
+            assert_eq!((id >> 12) & 0x3FF, 777);
+            assert_eq!(decode_sequence_id(id, &properties) as u64, id & 0xFFF);
+        }
+    }
+    #[test]
+    fn gen_id_node_subfields() {
+        use super::*;
+        let mut properties = SequenceProperties::new(SystemTime::now(), 10, 0, 12, 3, 1, 1500);
+        properties.set_node_subfields(&[("datacenter", 4), ("worker", 6)]);
+        properties.set_node_subfield("datacenter", 9);
+        properties.set_node_subfield("worker", 42);
+        assert_eq!(properties.node_id, (9 << 6) | 42);
+        let id = generate_id(&properties).expect("ERROR: Failed to generate ID.");
+        assert_eq!(decode_node_subfield(id, &properties, "datacenter"), Some(9));
+        assert_eq!(decode_node_subfield(id, &properties, "worker"), Some(42));
+        assert_eq!(decode_node_subfield(id, &properties, "process"), None);
+        assert_eq!(
+            decode_node_subfields(id, &properties),
+            vec![("datacenter".to_owned(), 9), ("worker".to_owned(), 42)]
+        );
+    }
+    #[test]
+    fn gen_id_extra_fields() {
+        use super::*;
+        let mut properties = SequenceProperties::new(SystemTime::now(), 9, 5, 11, 3, 0, 1500);
+        properties.add_field("type", 4);
+        properties.add_field("shard", 6);
+        assert_eq!(properties.timestamp_bits, 34);
+        properties.set_field_value("shard", 12);
+        let id =
+            generate_id_with(&properties, &[("type", 3)]).expect("ERROR: Failed to generate ID.");
+        assert_eq!(decode_field(id, &properties, "type"), Some(3));
+        assert_eq!(decode_field(id, &properties, "shard"), Some(12));
+        assert_eq!(decode_node_id(id, &properties), 5);
+        let id = generate_id(&properties).expect("ERROR: Failed to generate ID.");
+        assert_eq!(decode_field(id, &properties, "type"), Some(0));
+        let names: Vec<String> = decode_fields(id, &properties)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, ["timestamp", "type", "shard", "sequence", "node_id"]);
+        let sequence = properties.sequence.get();
+        assert!(matches!(
+            generate_id_with(&properties, &[("type", 16)]),
+            Err(SequenceGeneratorError::FieldOverflow { bits: 4, .. })
+        ));
+        assert!(matches!(
+            generate_id_with(&properties, &[("node_id", 1)]),
+            Err(SequenceGeneratorError::UnknownField(_))
+        ));
+        for reserved in ["node", "Timestamp", "node-id"] {
+            assert!(matches!(
+                properties.try_add_field(reserved, 2),
+                Err(SequenceGeneratorError::InvalidConfiguration(_))
+            ));
+        }
+        assert_eq!(properties.sequence.get(), sequence);
+    }
+    #[test]
+    fn gen_id_checksum() {
+        use super::*;
+        for algorithm in [ChecksumAlgorithm::Crc, ChecksumAlgorithm::Damm] {
+            let mut properties = SequenceProperties::new(SystemTime::now(), 9, 5, 11, 3, 1, 1500);
+            properties.set_checksum(algorithm, 6);
+            assert_eq!(properties.timestamp_bits, 37);
+            assert!(matches!(
+                properties.try_set_checksum(algorithm, 6),
+                Err(SequenceGeneratorError::InvalidConfiguration(_))
+            ));
+            assert_eq!(field(&properties, FieldKind::Checksum).shift, 0);
+            let id = generate_id(&properties).expect("ERROR: Failed to generate ID.");
+            assert!(validate_id(id, &properties).is_ok());
+            assert_eq!(decode_node_id(id, &properties), 5);
+            // Most mistyped decimal digits are reported as corrupted IDs, and
+            // so is any change to the checksum bits themselves
+            let digits = id.to_string().into_bytes();
+            let mut mistyped = 0;
+            let mut detected = 0;
+            for position in 0..digits.len() {
+                for digit in b'0'..=b'9' {
+                    let mut candidate = digits.clone();
+                    candidate[position] = digit;
+                    if candidate == digits {
+                        continue;
+                    }
+                    if let Ok(candidate) = String::from_utf8(candidate).unwrap().parse::<u64>() {
+                        mistyped += 1;
+                        detected += validate_id(candidate, &properties).is_err() as u32;
+                    }
+                }
+            }
+            assert!(detected * 4 >= mistyped * 3);
+            assert!(matches!(
+                validate_id(id ^ 1, &properties),
+                Err(SequenceGeneratorError::InvalidChecksum { .. })
+            ));
+        }
+    }
+    #[test]
+    fn min_id_bounds() {
+        use super::*;
+        let custom_epoch = UNIX_EPOCH + Duration::from_secs(1_577_836_800);
+        let mut properties = SequenceProperties::new(custom_epoch, 9, 511, 11, 3, 0, 1500);
+        let id = generate_id(&properties).unwrap();
+        let generated_at =
+            custom_epoch + Duration::from_micros(decode_timestamp_micros(id, &properties));
+        assert!(min_id_at(generated_at, &properties).unwrap() <= id);
+        assert!(min_id_at(generated_at + Duration::from_millis(1), &properties).unwrap() > id);
+        assert_eq!(
+            min_id_at(custom_epoch + Duration::from_micros(2500), &properties).unwrap(),
+            2 << 20
+        );
+        assert!(matches!(
+            min_id_at(custom_epoch - Duration::from_secs(1), &properties),
+            Err(SequenceGeneratorError::SystemTime(_))
+        ));
+        assert!(matches!(
+            min_id_at(custom_epoch + Duration::from_secs(1 << 44), &properties),
+            Err(SequenceGeneratorError::FieldOverflow { .. })
+        ));
+        properties.set_layout_version(1, 2);
+        assert_eq!(min_id_at(custom_epoch, &properties).unwrap(), 1 << 62);
+    }
 }
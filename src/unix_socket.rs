@@ -0,0 +1,140 @@
+//! Compact binary protocol of `sequence_generator serve --unix-socket`, so
+//! that the processes of a host share one generator and one node ID.
+//!
+//! Every integer is big-endian. A request is the number of IDs wanted as a
+//! `u32`. It is answered with the number of IDs returned as a `u32`
+//! followed by the packed `u64` IDs, or with a count of 0 followed by the
+//! length of an error message as a `u32` and the UTF-8 message.
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+/// Connection to a generator served over a Unix domain socket. Requests are
+/// sent one at a time and answered in order.
+#[derive(Debug)]
+pub struct UnixSocketClient {
+    stream: UnixStream,
+    buffer: Vec<u8>,
+}
+
+impl UnixSocketClient {
+    pub fn connect(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(UnixSocketClient {
+            stream: UnixStream::connect(path)?,
+            buffer: Vec::new(),
+        })
+    }
+    pub fn next_id(&mut self) -> io::Result<u64> {
+        let mut id = [0];
+        self.fill(&mut id)?;
+        Ok(id[0])
+    }
+    pub fn next_ids(&mut self, count: u32) -> io::Result<Vec<u64>> {
+        let mut ids = vec![0; count as usize];
+        self.fill(&mut ids)?;
+        Ok(ids)
+    }
+    /// Fill `ids` with consecutive IDs in a single request.
+    pub fn fill(&mut self, ids: &mut [u64]) -> io::Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let count = u32::try_from(ids.len()).map_err(|_| invalid_data("count too large"))?;
+        self.stream.write_all(&count.to_be_bytes())?;
+        let returned = read_u32(&mut self.stream)?;
+        if returned == 0 {
+            let length = read_u32(&mut self.stream)? as usize;
+            self.buffer.resize(length, 0);
+            self.stream.read_exact(&mut self.buffer)?;
+            let message = String::from_utf8_lossy(&self.buffer).into_owned();
+            return Err(io::Error::new(io::ErrorKind::Other, message));
+        }
+        if returned != count {
+            return Err(invalid_data(format!(
+                "{} IDs requested, {} returned",
+                count, returned
+            )));
+        }
+        self.buffer.resize(ids.len() * 8, 0);
+        self.stream.read_exact(&mut self.buffer)?;
+        for (id, bytes) in ids.iter_mut().zip(self.buffer.chunks_exact(8)) {
+            let mut id_bytes = [0; 8];
+            id_bytes.copy_from_slice(bytes);
+            *id = u64::from_be_bytes(id_bytes);
+        }
+        Ok(())
+    }
+}
+
+/// Count of the next request, `None` once the client closed the connection.
+pub fn read_request(reader: &mut impl Read) -> io::Result<Option<u32>> {
+    let mut count = [0; 4];
+    match reader.read_exact(&mut count) {
+        Ok(()) => Ok(Some(u32::from_be_bytes(count))),
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Answer a request with `ids`, in a single write.
+pub fn write_ids(writer: &mut impl Write, ids: &[u64]) -> io::Result<()> {
+    let mut response = Vec::with_capacity(4 + ids.len() * 8);
+    response.extend_from_slice(&(ids.len() as u32).to_be_bytes());
+    for id in ids {
+        response.extend_from_slice(&id.to_be_bytes());
+    }
+    writer.write_all(&response)
+}
+
+pub fn write_error(writer: &mut impl Write, message: &str) -> io::Result<()> {
+    let mut response = Vec::with_capacity(8 + message.len());
+    response.extend_from_slice(&0_u32.to_be_bytes());
+    response.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    response.extend_from_slice(message.as_bytes());
+    writer.write_all(&response)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut value = [0; 4];
+    reader.read_exact(&mut value)?;
+    Ok(u32::from_be_bytes(value))
+}
+
+fn invalid_data(message: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn request_over_unix_socket() {
+        use super::*;
+        use std::os::unix::net::UnixListener;
+        let path =
+            std::env::temp_dir().join(format!("sequence-generator-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut next = 1;
+            while let Some(count) = read_request(&mut stream).unwrap() {
+                if count > 3 {
+                    write_error(&mut stream, "too many").unwrap();
+                    continue;
+                }
+                let ids: Vec<u64> = (next..next + u64::from(count)).collect();
+                next += u64::from(count);
+                write_ids(&mut stream, &ids).unwrap();
+            }
+        });
+        let mut client = UnixSocketClient::connect(&path).unwrap();
+        assert_eq!(client.next_id().unwrap(), 1);
+        assert_eq!(client.next_ids(3).unwrap(), vec![2, 3, 4]);
+        let error = client.next_ids(4).unwrap_err();
+        assert_eq!(error.to_string(), "too many");
+        // Still usable after an error
+        assert_eq!(client.next_id().unwrap(), 5);
+        std::fs::remove_file(&path).unwrap();
+    }
+}